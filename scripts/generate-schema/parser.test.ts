@@ -126,6 +126,34 @@ Deno.test("parses simple objects", () => {
   );
 });
 
+Deno.test("parses schemas that accept any value", () => {
+  assertEquals(
+    parseSchema(makeSchema({
+      type: "object",
+      properties: {
+        value: true,
+      },
+      required: ["value"],
+    } as Partial<JSONSchema>)),
+    {
+      type: "doc",
+      title: "Test",
+      root: {
+        name: "Test",
+        type: "object",
+        properties: [
+          {
+            key: "value",
+            required: true,
+            value: { type: "unknown" },
+          },
+        ],
+      },
+      definitions: {},
+    },
+  );
+});
+
 Deno.test("parses discriminated unions", () => {
   assertEquals(
     parseSchema(makeSchema({
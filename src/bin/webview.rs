@@ -1,17 +1,53 @@
 use std::env;
 use tracing::error;
-use webview::{run, Options};
+use tracing_subscriber::layer::SubscriberExt;
+use webview::{check_options, doctor, options_json, run, Options, RpcLogLayer, OPTIONS_ENV_VAR};
 
 fn main() {
     let subscriber = tracing_subscriber::fmt()
         .with_env_filter(env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()))
         .with_writer(std::io::stderr)
-        .finish();
+        .finish()
+        .with(RpcLogLayer::default());
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     let args: Vec<String> = env::args().collect();
 
-    let webview_options: Options = match serde_json::from_str(&args[1]) {
+    // `webview --check '<json>'` validates the options and exits without opening a window.
+    if args.get(1).map(String::as_str) == Some("--check") {
+        let errors = check_options(args.get(2).map_or("", String::as_str));
+        println!(
+            "{}",
+            serde_json::json!({ "valid": errors.is_empty(), "errors": errors })
+        );
+        std::process::exit(if errors.is_empty() { 0 } else { 1 });
+    }
+
+    // `webview --doctor` checks that the environment can run the webview and exits.
+    if args.get(1).map(String::as_str) == Some("--doctor") {
+        let checks = doctor();
+        let ok = checks.iter().all(|check| check.ok);
+        println!(
+            "{}",
+            serde_json::json!({
+                "ok": ok,
+                "version": env!("CARGO_PKG_VERSION"),
+                "platform": env::consts::OS,
+                "checks": checks,
+            })
+        );
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let Some(json) = options_json(args.get(1).cloned()) else {
+        error!(
+            "No webview options given, pass them as the first argument or in `{}`",
+            OPTIONS_ENV_VAR
+        );
+        std::process::exit(1);
+    };
+
+    let webview_options: Options = match serde_json::from_str(&json) {
         Ok(options) => options,
         Err(e) => {
             error!("Failed to parse webview options: {:?}", e);
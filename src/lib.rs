@@ -1,27 +1,37 @@
 use actson::options::JsonParserOptionsBuilder;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use encoding_rs::Encoding;
 use parking_lot::Mutex;
 use std::borrow::Cow;
+#[cfg(target_os = "linux")]
+use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
 use std::io::{BufReader, Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::mpsc::{self, Sender};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tao::dpi;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tao::window::Fullscreen;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use tao::{
-    event::{Event, StartCause, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    event::{ElementState, Event, StartCause, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder},
     window::WindowBuilder,
 };
-use wry::http::header::{HeaderName, HeaderValue};
+use wry::http::header::{HeaderMap, HeaderName, HeaderValue};
 use wry::http::Response as HttpResponse;
-use wry::WebViewBuilder;
+use wry::{RequestAsyncResponder, WebContext, WebViewBuilder};
 
 use actson::feeder::BufReaderJsonFeeder;
 use actson::{JsonEvent, JsonParser};
@@ -29,6 +39,13 @@ use actson::{JsonEvent, JsonParser};
 /// The version of the webview binary.
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The version of the protocol spoken over stdio, bumped whenever a change would break clients.
+/// Independent of the crate version so clients can tell what a binary understands.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest protocol version clients may still speak.
+const MIN_PROTOCOL_VERSION: u32 = 1;
+
 #[derive(JsonSchema, Deserialize, Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Size {
@@ -49,6 +66,96 @@ pub struct SizeWithScale {
     scale_factor: f64,
 }
 
+#[derive(JsonSchema, Deserialize, Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    /// The x coordinate of the window in logical pixels.
+    x: f64,
+    /// The y coordinate of the window in logical pixels.
+    y: f64,
+}
+
+/// A rectangle in logical pixels.
+#[derive(JsonSchema, Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Rect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Rect {
+    fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Where to place a window relative to a rectangle on screen.
+#[derive(JsonSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Anchor {
+    /// The rectangle to place the window next to, in logical screen coordinates.
+    rect: Rect,
+    /// Which side of the rectangle to place the window on. The window is centered along that side.
+    placement: Placement,
+    /// The gap between the rectangle and the window in logical pixels. Default is 0.
+    #[serde(default)]
+    offset: f64,
+}
+
+/// Hosts the webview is allowed to navigate to.
+///
+/// A pattern matches a host exactly, or its subdomains when it starts with `*.`, e.g. `*.example.com`. Urls without a host, like `about:blank` and `data:` urls, and the webview's own `load-html` scheme are always allowed.
+#[derive(JsonSchema, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlFilter {
+    /// The hosts that are allowed. Every host is allowed if it's empty.
+    #[serde(default)]
+    allow: Vec<String>,
+    /// The hosts that are blocked, even if they're allowed by `allow`.
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl UrlFilter {
+    /// Whether `url` may be navigated to.
+    fn allows(&self, url: &str) -> bool {
+        let Ok(uri) = wry::http::Uri::from_str(url) else {
+            return true;
+        };
+        // Urls like `about:blank` parse as a bare host and port, so only urls with a scheme and a
+        // host are filtered.
+        let (Some(scheme), Some(host)) = (uri.scheme_str(), uri.host()) else {
+            return true;
+        };
+        if scheme == "load-html" {
+            return true;
+        }
+        let host = host.to_ascii_lowercase();
+        let matches = |pattern: &String| {
+            let pattern = pattern.to_ascii_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => host == pattern,
+            }
+        };
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
+
+/// A side of a rectangle.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Placement {
+    Above,
+    Below,
+    Left,
+    Right,
+}
+
 #[derive(JsonSchema, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub enum WindowSizeStates {
@@ -56,11 +163,25 @@ pub enum WindowSizeStates {
     Fullscreen,
 }
 
+/// A window state along with the size the window returns to when it leaves that state.
+#[derive(JsonSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowSizeStateWithSize {
+    /// The state the window starts in.
+    state: WindowSizeStates,
+    /// The width of the window in logical pixels when it's restored.
+    width: f64,
+    /// The height of the window in logical pixels when it's restored.
+    height: f64,
+}
+
 #[derive(JsonSchema, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
 pub enum WindowSize {
     States(WindowSizeStates),
+    // Before `Size`, which would otherwise match and drop the state.
+    StateWithSize(WindowSizeStateWithSize),
     Size(Size),
 }
 
@@ -74,28 +195,123 @@ pub struct Options {
     #[serde(default)]
     load: Option<Content>,
     /// The size of the window.
+    ///
+    /// Either a size, a state such as `maximized`, or a state along with the size the window is restored to, e.g. `{ "state": "maximized", "width": 800, "height": 600 }`.
     #[serde(default)]
     size: Option<WindowSize>,
     /// When true, the window will have a border, a title bar, etc. Default is true.
     #[serde(default = "default_true")]
     decorations: bool,
-    /// Sets whether the window should be transparent.
+    /// When false, the user can't close the window, e.g. for kiosks and unattended displays. Attempts to, like clicking the close button or pressing Alt+F4, send a `closeBlocked` notification instead. Default is true.
+    ///
+    /// The webview can still be shut down with a `close` request or by sending the process a termination signal.
+    ///
+    /// Platform-specific:
+    /// - Linux: The close button is only hidden by window managers that respect it.
+    #[serde(default = "default_true")]
+    closable: bool,
+    /// Makes the top of the page a handle for dragging the window, in logical pixels, e.g. for a custom title bar in a window without `decorations`. Default is 0.
+    ///
+    /// Pressing the left mouse button there moves the window, unless it's on a link, a form control or an element inside one with a `data-webview-no-drag` attribute, or the page called `preventDefault` on the `mousedown`. It doesn't apply inside frames.
+    #[serde(default)]
+    drag_region_height: f64,
+    /// When true, pages get touch events for the left mouse button, to try touch interfaces on a desktop, e.g. for touch kiosks. Default is false.
+    ///
+    /// `touchstart`, `touchmove` and `touchend` are dispatched before the mouse events they stand in for, which are dropped when a touch listener calls `preventDefault`. `navigator.maxTouchPoints` reports 1 while it's on. It's only an approximation of a touch screen:
+    /// - There's a single touch point, so multi-touch gestures like pinching can't be tried.
+    /// - Dragging doesn't scroll the page and there's no radius, force or tap highlight.
+    /// - Pointer events still have a `pointerType` of `mouse`, and hover styles still apply.
+    /// - Feature checks like `"ontouchstart" in window` and the `pointer: coarse` media query are unaffected.
+    #[serde(default)]
+    emulate_touch: bool,
+    /// When true, the window is kept below all other windows, e.g. for desktop widgets or live wallpapers. Default is false.
+    ///
+    /// Platform-specific:
+    /// - **Windows**: The window tries to stay at the bottom but it isn't guaranteed.
+    #[serde(default)]
+    always_on_bottom: bool,
+    /// The kind of window this is, which window managers use to decide how to place it, e.g. tiling window managers float `utility` and `dialog` windows rather than tiling them.
+    ///
+    /// Platform-specific:
+    /// - Linux: Sets the window's type hint.
+    /// - macOS / Windows: Unsupported, it's ignored.
+    window_type: Option<WindowType>,
+    /// Sets whether the window and the webview's background should be transparent.
+    ///
+    /// Only the parts of the page without a background show through, so pages that set one stay opaque. Use `transparentHtml` to clear the background of html and directory content.
+    ///
+    /// Platform-specific:
+    /// - Linux: Needs a compositor and a screen with an RGBA visual, a warning is logged when either is missing and the window is drawn opaque.
     #[serde(default)]
     transparent: bool,
+    /// The theme the window is pinned to. When not set, the window follows the system's theme and `themeChanged` notifications are sent when it changes. See the `setFollowSystemTheme` request to change it later.
+    ///
+    /// Platform-specific:
+    /// - Linux: The theme is app-wide rather than specific to the window.
+    #[serde(default)]
+    theme: Option<Theme>,
+    /// When true, `requestAnimationFrame` callbacks are held back while the window is hidden or minimized, saving battery for long running pages. Default is false.
+    ///
+    /// The page is also sent `webviewpause` and `webviewresume` events on `window` so it can pause anything else, such as timers or media. CSS animations, timers, media and animations in iframes aren't paused.
+    #[serde(default)]
+    pause_animations_when_hidden: bool,
+    /// When true, the system running low on memory is reported with `memoryPressure` notifications, so the client can react before processes are killed. Default is false.
+    ///
+    /// Platform-specific:
+    /// - Linux: Reported by GLib's memory monitor, which needs the `xdg-desktop-portal` memory monitor or a kernel with pressure stall information.
+    /// - macOS / Windows: Unsupported, no notifications are sent.
+    #[serde(default)]
+    report_memory_pressure: bool,
+    /// When true, the webview frees what it can when the system is low on memory: it clears its memory cache and sends the page a `webviewmemorypressure` event on `window`, with the level as its `detail`, so the page can drop its own caches. Default is false.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported, the same as `reportMemoryPressure`.
+    #[serde(default)]
+    release_memory_on_pressure: bool,
+    /// When true, the `html` and `body` backgrounds of html and directory content are made transparent, including content loaded later with `loadHtml` and `loadDirectory`. Default is false.
+    ///
+    /// URLs are left as they are. This only has a visible effect when `transparent` is also set, e.g. for overlays.
+    #[serde(default)]
+    transparent_html: bool,
+    /// A web app manifest for html and directory content, e.g. `{ "name": "Notes", "display": "standalone", "theme_color": "#1e1e1e" }`, for apps moved over from a PWA.
+    ///
+    /// It's served as `application/manifest+json` from `/__webview/manifest.webmanifest` of the `load-html` protocol, and a `<link rel="manifest">` to it is added to each page along with a `<meta name="theme-color">` when it has a `theme_color`. URLs are left as they are.
+    ///
+    /// Only what the browser engine reads from the manifest and `theme-color` takes effect, which outside of installed apps is often nothing.
+    #[serde(default)]
+    manifest: Option<serde_json::Value>,
     /// When true, all media can be played without user interaction. Default is false.
     #[serde(default)]
     autoplay: bool,
+    /// Makes the window fullscreen while an element of the page is fullscreen, e.g. a video player. Default is false.
+    ///
+    /// Platform-specific:
+    /// - Linux: WebKitGTK already does this by itself.
+    #[serde(default)]
+    follow_page_fullscreen: bool,
+    /// Which media can be played without user interaction, taking precedence over `autoplay`.
+    ///
+    /// `allowMuted` enables autoplay, then stops audible playback that doesn't start within 5 seconds of a click, tap or key press, including media unmuted while it plays. This is enforced by a script, so it only applies to the top frame.
+    autoplay_policy: Option<AutoplayPolicy>,
     /// Enable or disable webview devtools.
     ///
     /// Note this only enables devtools to the webview. To open it, you can call `webview.open_devtools()`, or right click the page and open it from the context menu.
     #[serde(default)]
     devtools: bool,
+    /// Opens devtools as soon as the webview is created. Requires `devtools` to be enabled. Default is false.
+    #[serde(default)]
+    open_devtools_on_start: bool,
     /// Run the WebView with incognito mode. Note that WebContext will be ingored if incognito is enabled.
     ///
     /// Platform-specific:
     /// - Windows: Requires WebView2 Runtime version 101.0.1210.39 or higher, does nothing on older versions, see https://learn.microsoft.com/en-us/microsoft-edge/webview2/release-notes/archive?tabs=dotnetcsharp#10121039
     #[serde(default)]
     incognito: bool,
+    /// Stores the webview's profile in a new temporary directory that's deleted when the webview exits, giving every launch a fresh profile without incognito's limitations. Ignored if `incognito` is enabled. Default is false.
+    ///
+    /// The directory is left behind if the process is killed or crashes, it's created in the system's temporary directory so it's cleaned up with it.
+    #[serde(default)]
+    ephemeral_profile: bool,
     /// Enables clipboard access for the page rendered on Linux and Windows.
     ///
     /// macOS doesn’t provide such method and is always enabled by default. But your app will still need to add menu item accelerators to use the clipboard shortcuts.
@@ -108,6 +324,10 @@ pub struct Options {
     #[serde(default)]
     accept_first_mouse: bool,
     /// Sets whether host should be able to receive messages from the webview via `window.ipc.postMessage`.
+    ///
+    /// The page can also send a message it expects an answer to with `await window.webviewRequest(message)`, which posts the message with a `__rpc_id` field added and resolves with the data the client sends back in a `respondToPage` request. Only the top frame can.
+    ///
+    /// `window.ipc` is defined on every page either way, since the webview's own scripts report back through it, but the page's messages are dropped when this is false.
     #[serde(default)]
     ipc: bool,
     #[serde(default)]
@@ -116,12 +336,331 @@ pub struct Options {
     /// Sets the user agent to use when loading pages.
     #[serde(default)]
     user_agent: Option<String>,
+    /// The minimum number of milliseconds between `resized` and `moved` notifications while the window is being resized or dragged.
+    ///
+    /// Intermediate values are coalesced and the latest one is always sent once the window settles. Set to 0 to send every event. Default is 100.
+    #[serde(default = "default_window_event_throttle_ms")]
+    window_event_throttle_ms: u64,
+    /// A directory to watch for changes. When any file in it changes the webview is reloaded.
+    ///
+    /// This is intended for local development and requires the binary to be built with the `watch` feature.
+    #[serde(default)]
+    watch: Option<String>,
+    /// How to respond when a page asks for access to the camera, microphone, location, etc.
+    ///
+    /// Platform-specific:
+    /// - Linux: All policies are supported.
+    /// - macOS / Windows: Only `platform` is supported. The platform grants media capture and denies everything else.
+    #[serde(default)]
+    permissions: PermissionPolicy,
+    /// The maximum number of requests that can be waiting to be processed. Default is 1024.
+    #[serde(default = "default_max_queued_requests")]
+    max_queued_requests: usize,
+    /// What to do with new requests when `maxQueuedRequests` has been reached. Default is `reject`.
+    #[serde(default)]
+    queue_overflow: OverflowPolicy,
+    /// Headers added to every response served when loading html, a directory or from `customProtocols`, such as a `Content-Security-Policy` or `Cache-Control`.
+    ///
+    /// These take precedence over the headers set by the webview, including `Content-Type`. Invalid headers are ignored.
+    #[serde(default)]
+    protocol_headers: HashMap<String, String>,
+    /// Sent as the `Content-Security-Policy` header of every response served when loading html, a directory or from `customProtocols`, replacing one set in `protocolHeaders`.
+    ///
+    /// Each `{nonce}` in the policy is replaced with a new random nonce for every response, which is also added to the `<script>` and `<style>` tags of html responses. A policy like `script-src 'nonce-{nonce}'` then allows the app's own scripts while blocking scripts injected into the page later. Only tags in the served html get the nonce, not ones created by scripts.
+    content_security_policy: Option<String>,
+    /// When true, violations of the page's content security policy are sent to the client as `cspViolation` notifications. Default is false.
+    #[serde(default)]
+    report_csp_violations: bool,
+    /// Sets `history.scrollRestoration` on every page that's loaded.
+    ///
+    /// This only affects history navigations (e.g. going back or forward) and is useful for apps that manage their own scroll position.
+    #[serde(default)]
+    scroll_restoration: Option<ScrollRestoration>,
+    /// When true, the page's `document.title` is kept in sync with the window title and any attempt by the page to change it is reverted. Default is false.
+    ///
+    /// Each reverted attempt is reported with a `titleOverridden` notification. Useful when embedding third-party content whose title shouldn't show through.
+    #[serde(default)]
+    lock_title: bool,
+    /// Forwards requests for a custom URL scheme to the client as `protocolRequest` notifications, letting the client act as a backend for the page.
+    #[serde(default)]
+    client_protocol: Option<ClientProtocol>,
+    /// When true, the window starts hidden and is only shown once the page has painted, avoiding a blank flash on launch. Default is false.
+    ///
+    /// A `firstPaint` notification is sent when the window is shown.
+    #[serde(default)]
+    show_on_first_paint: bool,
+    /// When true, the platform's default error page isn't shown when a page fails to load. Default is false.
+    ///
+    /// The failed load is reported with a `loadFailed` notification either way. `errorPageHtml` is shown instead if set, otherwise the page is left blank.
+    ///
+    /// Platform-specific:
+    /// - **macOS / Windows**: Unsupported.
+    #[serde(default)]
+    suppress_error_pages: bool,
+    /// Html to show in place of the default error page when `suppressErrorPages` is set.
+    #[serde(default)]
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    error_page_html: Option<String>,
+    /// Serves html and directory content over `https` instead of `http` so it's a secure context, allowing APIs like `crypto.subtle` and service workers. Default is false.
+    ///
+    /// Note that this changes the page's origin, so data in storage such as `localStorage` isn't shared with pages served over `http`.
+    ///
+    /// Platform-specific:
+    /// - Windows: Pages are served from `https://load-html.<origin>` instead of `http://load-html.<origin>`.
+    /// - macOS / Linux: Does nothing, custom protocols are already treated as secure contexts.
+    #[serde(default)]
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    https_scheme: bool,
+    /// Extra command line arguments passed to the browser process, e.g. `--autoplay-policy=no-user-gesture-required`.
+    ///
+    /// These replace the arguments passed by default, `--disable-features=msWebOOUI,msPdfOOUI,msSmartScreenProtection --enable-features=RemoveRedirectionBitmap`, plus `--autoplay-policy=no-user-gesture-required` when `autoplay` is enabled, so include them if they're still wanted.
+    ///
+    /// Platform-specific:
+    /// - Windows: Passed to WebView2.
+    /// - macOS / Linux: Unsupported, it's ignored.
+    additional_browser_args: Option<String>,
+    /// The AppUserModelID the taskbar groups the window by, e.g. `com.example.App`. It should match the one of the app's shortcut so the window gets its icon and jump list.
+    ///
+    /// Platform-specific:
+    /// - Windows: Set for the whole process.
+    /// - macOS / Linux: Unsupported, it's ignored.
+    app_user_model_id: Option<String>,
+    /// When true, every request and response is logged at `info` level along with how long the request took to answer. Default is false.
+    ///
+    /// Useful for finding requests that are slow or never answered.
+    #[serde(default)]
+    trace_rpc: bool,
+    /// When true, the JSON of every request the webview reads is sent back in a `requestReceived` notification, as the webview reassembled it before parsing, to debug how requests are escaped or framed. Default is false.
+    ///
+    /// This is a debugging aid that doubles the output, with large requests like `loadHtml` copied in full, so it shouldn't be left on.
+    #[serde(default)]
+    debug_echo_requests: bool,
+    /// When true, log records are also sent to the client as `log` notifications, for clients that can't read stderr. Default is false.
+    ///
+    /// Records are filtered by `LOG_LEVEL` like those written to stderr and are rate limited, with a warning sent when some are dropped.
+    #[serde(default)]
+    logs_over_rpc: bool,
+    /// When true, the user can't zoom the page with a pinch gesture or ctrl + scroll. Default is false.
+    ///
+    /// Scrolling, including with touch and trackpad gestures, isn't affected.
+    #[serde(default)]
+    disable_zoom: bool,
+    /// Whether scrolling is animated, overriding the page's `scroll-behavior` style. The page decides if it's not set.
+    ///
+    /// Platform-specific:
+    /// - Linux: Also animates scrolling with the mouse wheel and keyboard.
+    smooth_scrolling: Option<bool>,
+    /// Whether scrolling past the edge of the page bounces or scrolls the page behind, overriding the page's `overscroll-behavior` style. The page decides if it's not set.
+    overscroll: Option<bool>,
+    /// Whether the page's scrollbars are shown. When false they're hidden with a stylesheet and the page can still be scrolled, e.g. for kiosks. Default is true.
+    #[serde(default = "default_true")]
+    scrollbars_visible: bool,
+    /// Scales the page's text by this factor without scaling images or the rest of the layout, see `setTextZoom`.
+    text_zoom: Option<f64>,
+    /// Keeps the window's inner width divided by its height at this ratio when it's resized, e.g. `1.7778` for 16:9 video.
+    ///
+    /// The height follows the width, so this wins over the height of `resizeIncrements` when both are set.
+    #[serde(default)]
+    aspect_ratio: Option<f64>,
+    /// Snaps the window's inner size to multiples of this size when it's resized, e.g. the size of a character cell in a terminal.
+    #[serde(default)]
+    resize_increments: Option<Size>,
+    /// When true, key presses and releases in the window are sent to the client as `keyDown` and `keyUp` notifications, e.g. to implement shortcuts in the client. Default is false.
+    ///
+    /// The page still receives its own keyboard events and can't prevent these from being sent. Platforms may not report keys the focused web content handles, so shortcuts that must always work are best registered in the page as well.
+    #[serde(default)]
+    forward_keyboard: bool,
+    /// Warns when a message to the client hasn't been read for this many milliseconds, which usually means the client stopped reading the webview's output and the two are deadlocked.
+    #[serde(default)]
+    output_deadline_ms: Option<u64>,
+    /// When true, the webview exits instead of only warning once `outputDeadlineMs` has passed. Default is false.
+    #[serde(default)]
+    exit_on_output_deadline: bool,
+    /// Headers sent with every navigation to an `http` or `https` url, e.g. an `Authorization` header that should survive link clicks within the app. Headers passed when loading a url take precedence.
+    ///
+    /// The platforms only add headers to the first request of a load, so navigations started by the page are stopped and restarted with the headers added. As a result, form submissions are resent without their body.
+    #[serde(default)]
+    default_headers: HashMap<String, String>,
+    /// The language pages should be shown in, as a language tag like `de-DE`. It's sent as the `Accept-Language` header, unless `defaultHeaders` already sets one.
+    ///
+    /// Platform-specific:
+    /// - Linux: Also sets the language reported by `navigator.language`, and the header's sent with every request rather than only navigations.
+    /// - macOS / Windows: Sent with navigations to `http` and `https` urls the same way as `defaultHeaders`, with the same caveats.
+    language: Option<String>,
+    /// The `Referer` header sent when loading a url, for sites that only serve requests from a specific referer. `loadUrl` requests can override it, and it takes precedence over a `Referer` in `defaultHeaders`.
+    ///
+    /// It's sent with navigations to `http` and `https` urls the same way as `defaultHeaders`, with the same caveats. Requests the page makes for its resources keep the referer the browser computes from the page.
+    referer: Option<String>,
+    /// The referrer policy of loaded pages, controlling what's sent as the `Referer` of the page's own requests and navigations. It's applied by a `<meta name="referrer">` element added to each page, so it only applies to the top frame and a page's own referrer policy takes precedence.
+    referrer_policy: Option<ReferrerPolicy>,
+    /// Restricts which hosts the webview can navigate to. Blocked navigations are reported with a `requestBlocked` notification.
+    ///
+    /// Only navigations of the page or its frames are filtered, the platforms don't let requests for its resources, like images, scripts or `fetch` calls, be blocked.
+    url_filter: Option<UrlFilter>,
+    /// Hides or moves the close, minimize and zoom buttons in the title bar, e.g. to fit a custom title bar.
+    ///
+    /// Platform-specific:
+    /// - **Linux / Windows**: Unsupported.
+    #[serde(default)]
+    traffic_lights: Option<TrafficLights>,
+    /// Html shown right away while the url given in `load` loads behind it, replacing the blank window during slow loads. Only used when loading a url.
+    ///
+    /// The url replaces the splash once it has loaded, which is reported with a `splashDismissed` notification.
+    #[serde(default)]
+    splash_html: Option<String>,
+    /// Where to place the window's top left corner when it's created, in logical screen coordinates. Coordinates can be negative for monitors above or to the left of the primary one.
+    ///
+    /// Has no effect when `anchor` is set. The window manager decides where the window goes if it's not set.
+    ///
+    /// Platform-specific:
+    /// - Linux: Wayland doesn't let windows position themselves, so it's ignored there.
+    position: Option<Position>,
+    /// Positions the window next to a rectangle on screen, e.g. the element that opened a popover. The window is kept within the bounds of its monitor.
+    #[serde(default)]
+    anchor: Option<Anchor>,
+    /// Additional custom protocols, mapping a scheme to a local directory whose files are served for it.
+    ///
+    /// For example `{ "assets": "./public" }` serves `assets://localhost/logo.png` from `./public/logo.png`. The `load-html` and `webview-font` schemes are reserved.
+    #[serde(default)]
+    custom_protocols: HashMap<String, String>,
+    /// Font files made available to every page with `@font-face` rules, so bundled html can use them without network access.
+    ///
+    /// The fonts are served from the `webview-font` scheme. A page's content security policy needs to allow it in `font-src`, e.g. `font-src webview-font:` or `font-src http://webview-font.localhost` on Windows.
+    #[serde(default)]
+    fonts: Vec<Font>,
+}
+
+/// A font file made available to pages by the `fonts` option.
+#[derive(JsonSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Font {
+    /// The name pages use for the font in `font-family`.
+    family: String,
+    /// Path to a `woff2`, `woff`, `ttf` or `otf` file.
+    path: Option<String>,
+    /// The base64 encoded font file, used when `path` isn't set.
+    data: Option<String>,
+    /// The `font-weight` the font is used for, e.g. `700`, or `100 900` for a variable font. Default is `normal`.
+    weight: Option<String>,
+    /// The `font-style` the font is used for, e.g. `italic`. Default is `normal`.
+    style: Option<String>,
+}
+
+/// How to show the close, minimize and zoom buttons in the title bar on macOS.
+#[derive(JsonSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub struct TrafficLights {
+    /// Whether the buttons are shown. Default is true.
+    #[serde(default = "default_true")]
+    visible: bool,
+    /// The position of the buttons relative to the top-left corner of the window, for custom title bars.
+    #[serde(default)]
+    inset: Option<Position>,
+}
+
+/// Shows or hides the close, minimize and zoom buttons in the title bar.
+#[cfg(target_os = "macos")]
+fn set_traffic_lights_visible(window: &tao::window::Window, visible: bool) {
+    use objc2_app_kit::{NSWindow, NSWindowButton};
+    use tao::platform::macos::WindowExtMacOS;
+
+    // SAFETY: `ns_window` points to the window's `NSWindow`, which lives as long as `window`.
+    let ns_window = unsafe { &*window.ns_window().cast::<NSWindow>() };
+    for button in [
+        NSWindowButton::CloseButton,
+        NSWindowButton::MiniaturizeButton,
+        NSWindowButton::ZoomButton,
+    ] {
+        if let Some(button) = ns_window.standardWindowButton(button) {
+            button.setHidden(!visible);
+        }
+    }
+}
+
+/// The progress of replacing the `splashHtml` with the url being loaded.
+enum Splash {
+    /// The splash is loading or shown, the url hasn't started loading yet.
+    Showing {
+        url: String,
+        headers: Option<HashMap<String, String>>,
+    },
+    /// The url is loading while the splash stays on screen.
+    Loading,
+}
+
+/// A custom URL scheme whose requests are answered by the client.
+#[derive(JsonSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientProtocol {
+    /// The scheme to handle, e.g. `app` for `app://localhost/api`. Must not be `load-html`.
+    scheme: String,
+    /// How long to wait for the client's `protocolResponse` before failing the request with a 504. Default is 30000.
+    #[serde(default = "default_client_protocol_timeout_ms")]
+    timeout_ms: u64,
+}
+
+fn default_client_protocol_timeout_ms() -> u64 {
+    30000
+}
+
+/// Whether the browser restores the scroll position when navigating through history.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ScrollRestoration {
+    /// The browser restores the scroll position.
+    Auto,
+    /// The page is responsible for restoring the scroll position.
+    Manual,
+}
+
+impl ScrollRestoration {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ScrollRestoration::Auto => "auto",
+            ScrollRestoration::Manual => "manual",
+        }
+    }
+}
+
+fn default_max_queued_requests() -> usize {
+    1024
+}
+
+/// What to do with a request that arrives while the request queue is full.
+#[derive(JsonSchema, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// Respond to the request with an error without processing it.
+    #[default]
+    Reject,
+    /// Stop reading input until there's room in the queue.
+    Block,
+}
+
+/// How permission requests from the page are handled.
+#[derive(JsonSchema, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PermissionPolicy {
+    /// Leave the decision to the platform's default behavior.
+    #[default]
+    Platform,
+    /// Grant every permission request.
+    Allow,
+    /// Deny every permission request.
+    Deny,
+    /// Send a `permissionRequested` notification and wait for a `permissionDecision` request from the client.
+    Ask,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_window_event_throttle_ms() -> u64 {
+    100
+}
+
 /// The content to load into the webview.
 #[derive(JsonSchema, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -139,6 +678,18 @@ pub enum Content {
         /// What to set as the origin of the webview when loading html.
         #[serde(default = "default_origin")]
         origin: String,
+        /// The charset the html is encoded in and declared with in its `Content-Type`, any label from the Encoding Standard such as `iso-8859-1` or `shift_jis`. Default is `utf-8`.
+        ///
+        /// Characters the charset can't represent are sent as numeric character references. UTF-16 labels are sent as UTF-8.
+        #[serde(default = "default_charset")]
+        charset: String,
+    },
+    Directory {
+        /// Path to a local directory whose files will be served to the webview.
+        path: String,
+        /// The file within the directory to load first. Default is `index.html`.
+        #[serde(default = "default_entry")]
+        entry: String,
     },
 }
 
@@ -147,6 +698,16 @@ fn default_origin() -> String {
     "init".to_string()
 }
 
+/// The default charset html is served with.
+fn default_charset() -> String {
+    "utf-8".to_string()
+}
+
+/// The default file to load when serving a directory.
+fn default_entry() -> String {
+    "index.html".to_string()
+}
+
 // --- RPC Definitions ---
 
 /// Complete definition of all outbound messages from the webview to the client.
@@ -163,75 +724,436 @@ pub enum Message {
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "$type")]
 pub enum Notification {
+    /// The window and webview were created and requests can be sent. It's always the first message and is only sent once the webview has been built, if that fails an `error` and a `closed` notification are sent instead.
+    ///
+    /// The page may still be loading, requests that need it like `eval` run against whatever has loaded so far. Use `whenReady` to wait for the page.
     Started {
         /// The version of the webview binary
         version: String,
+        /// The newest version of the protocol the webview speaks, see the `hello` request.
+        protocol_version: u32,
     },
     Ipc {
         /// The message sent from the webview UI to the client.
         message: String,
+        /// Increases by one with every IPC message starting at 0, so gaps or reordering can be detected.
+        seq: u64,
+        /// The `__rpc_id` of a message sent with `window.webviewRequest`, which is waiting for a `respondToPage` request with it.
+        rpc_id: Option<u64>,
     },
-    Closed,
-}
-
-/// Explicit requests from the client to the webview.
-#[derive(JsonSchema, Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "$type")]
-pub enum Request {
-    GetVersion {
-        /// The id of the request.
-        id: i64,
+    Resized {
+        /// The new inner size of the window.
+        size: SizeWithScale,
     },
-    Eval {
-        /// The id of the request.
-        id: i64,
-        /// The javascript to evaluate.
-        js: String,
+    Moved {
+        /// The new position of the top-left corner of the window.
+        position: Position,
     },
-    SetTitle {
-        /// The id of the request.
-        id: i64,
-        /// The title to set.
-        title: String,
+    Reloaded {
+        /// Why the webview was reloaded.
+        reason: ReloadReason,
     },
-    GetTitle {
-        /// The id of the request.
-        id: i64,
+    PermissionRequested {
+        /// The id to use when responding with a `permissionDecision` request.
+        request_id: u64,
+        /// The origin of the page requesting the permission.
+        origin: String,
+        /// The kind of permission being requested.
+        kind: PermissionKind,
     },
-    SetVisibility {
-        /// The id of the request.
-        id: i64,
-        /// Whether the window should be visible or hidden.
-        visible: bool,
+    /// The first page finished painting, sent when `showOnFirstPaint` is set.
+    FirstPaint,
+    /// An element of the page entered or left fullscreen with the Fullscreen API, e.g. a video player.
+    PageFullscreenChanged {
+        /// Whether an element is now fullscreen.
+        fullscreen: bool,
     },
-    IsVisible {
-        /// The id of the request.
-        id: i64,
+    /// The system is running low on memory, sent when `reportMemoryPressure` is set.
+    MemoryPressure {
+        /// How low memory is.
+        level: MemoryPressureLevel,
     },
-    OpenDevTools {
-        /// The id of the request.
-        id: i64,
+    /// The JSON of a request the webview read, sent when `debugEchoRequests` is set. It's sent before the request is parsed, so requests that fail to parse are included.
+    RequestReceived {
+        /// The request as the webview reassembled it from its input.
+        raw: String,
     },
-    GetSize {
-        /// The id of the request.
-        id: i64,
-        /// Whether to include the title bar and borders in the size measurement.
-        #[serde(default)]
-        include_decorations: Option<bool>,
+    /// The system's theme changed while the window follows it, see the `theme` option.
+    ///
+    /// Platform-specific:
+    /// - Linux: Unsupported, changes of the system's theme aren't detected.
+    ThemeChanged {
+        /// The window's new theme.
+        theme: Theme,
     },
-    SetSize {
-        /// The id of the request.
-        id: i64,
-        /// The size to set.
-        size: Size,
+    /// The user tried to close the window while `closable` is false, the window was kept open.
+    CloseBlocked,
+    /// The url finished loading and replaced the `splashHtml`.
+    SplashDismissed,
+    /// The page's web content process was replaced by a `recreateWebview` request and the page is reloading.
+    WebviewRecreated,
+    /// The result of a `findInPage` request.
+    FindResult {
+        /// The number of matches in the page.
+        matches: u32,
+        /// The position of the highlighted match among them, starting at 1. It's 0 when there are no matches.
+        active: u32,
     },
-    Fullscreen {
-        /// The id of the request.
-        id: i64,
-        /// Whether to enter fullscreen mode.
-        /// If left unspecified, the window will enter fullscreen mode if it is not already in fullscreen mode
-        /// or exit fullscreen mode if it is currently in fullscreen mode.
+    /// The page violated its content security policy, sent when `reportCspViolations` is set.
+    CspViolation {
+        /// The directive that was violated, e.g. `script-src-elem`.
+        directive: String,
+        /// The url of the blocked resource, or `inline` or `eval` for blocked inline scripts and styles or `eval` calls.
+        blocked_uri: String,
+        /// The url of the script or document that caused the violation.
+        source_file: Option<String>,
+        /// The line in `source_file` that caused the violation.
+        line_number: Option<u32>,
+        /// The column in `source_file` that caused the violation.
+        column_number: Option<u32>,
+        /// The start of the blocked inline script or style, if the policy asks for it with `'report-sample'`.
+        sample: Option<String>,
+        /// Whether the resource was blocked, it's false for policies that only report violations.
+        enforced: bool,
+    },
+    /// A navigation was blocked by the `urlFilter` option.
+    RequestBlocked {
+        /// The url that was blocked.
+        url: String,
+    },
+    /// The page made a request to the `clientProtocol` scheme. Answer it with a `protocolResponse` request.
+    ProtocolRequest {
+        /// The id to send back in the `protocolResponse`.
+        request_id: u64,
+        /// The full url that was requested.
+        url: String,
+        /// The HTTP method of the request.
+        method: String,
+        /// The request headers. Repeated headers are joined with `, `.
+        headers: HashMap<String, String>,
+        /// The base64 encoded request body.
+        body: String,
+    },
+    /// A page failed to load. Only sent on Linux.
+    LoadFailed {
+        /// The url that failed to load.
+        url: String,
+        /// A description of the failure.
+        message: String,
+    },
+    /// A log record, sent when `logsOverRpc` is set.
+    Log {
+        /// The severity of the record.
+        level: LogLevel,
+        /// The module the record came from.
+        target: String,
+        /// The formatted message, followed by any other fields.
+        message: String,
+    },
+    /// The page tried to change its title while `lockTitle` is set, the change was reverted.
+    TitleOverridden {
+        /// The title the page tried to set.
+        title: String,
+    },
+    /// A key was pressed while the window was focused, sent when `forwardKeyboard` is set.
+    KeyDown {
+        /// The key that was pressed, e.g. `a`, `Enter` or `ArrowLeft`.
+        key: String,
+        /// The physical key that was pressed regardless of the keyboard layout, e.g. `KeyA`.
+        code: String,
+        /// The modifier keys held down when the key was pressed.
+        modifiers: KeyModifiers,
+        /// Whether the key is being held down and this is a repeat.
+        repeat: bool,
+    },
+    /// A key was released while the window was focused, sent when `forwardKeyboard` is set.
+    KeyUp {
+        /// The key that was released.
+        key: String,
+        /// The physical key that was released regardless of the keyboard layout.
+        code: String,
+        /// The modifier keys held down when the key was released.
+        modifiers: KeyModifiers,
+        /// Always false.
+        repeat: bool,
+    },
+    /// A file is being dragged over the window. Sent once per file.
+    ///
+    /// File drag and drop notifications come from the native window, so they're only sent for drops the webview doesn't handle itself. Drops onto the page are delivered to it as regular drag and drop events instead, so the same drop never produces both.
+    FileHovered {
+        /// The path of the file.
+        path: String,
+    },
+    /// A file was dropped onto the window. Sent once per file.
+    FileDropped {
+        /// The path of the file.
+        path: String,
+    },
+    /// The files being dragged over the window left it without being dropped.
+    FileHoverCancelled,
+    /// The webview failed to start, e.g. because there's no display to open a window on.
+    Error {
+        /// A description of what went wrong.
+        message: String,
+    },
+    Closed {
+        /// Why the webview closed.
+        reason: CloseReason,
+    },
+}
+
+/// The light or dark appearance of the window.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn from_tao(theme: tao::window::Theme) -> Self {
+        match theme {
+            tao::window::Theme::Dark => Theme::Dark,
+            _ => Theme::Light,
+        }
+    }
+
+    fn to_tao(self) -> tao::window::Theme {
+        match self {
+            Theme::Light => tao::window::Theme::Light,
+            Theme::Dark => tao::window::Theme::Dark,
+        }
+    }
+}
+
+/// How low the system is on memory.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum MemoryPressureLevel {
+    /// Memory is getting low, caches that are cheap to rebuild should be dropped.
+    Low,
+    /// Memory is low, processes that use a lot of it may be killed.
+    Medium,
+    /// Memory is almost exhausted, processes are about to be killed.
+    Critical,
+}
+
+/// The modifier keys held down during a keyboard event.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyModifiers {
+    shift: bool,
+    control: bool,
+    alt: bool,
+    /// The Windows key on Windows, the Command key on macOS.
+    meta: bool,
+}
+
+impl KeyModifiers {
+    fn from_tao(modifiers: tao::keyboard::ModifiersState) -> Self {
+        KeyModifiers {
+            shift: modifiers.shift_key(),
+            control: modifiers.control_key(),
+            alt: modifiers.alt_key(),
+            meta: modifiers.super_key(),
+        }
+    }
+}
+
+/// The severity of a log record.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn from_tracing(level: &tracing::Level) -> Self {
+        match *level {
+            tracing::Level::TRACE => LogLevel::Trace,
+            tracing::Level::DEBUG => LogLevel::Debug,
+            tracing::Level::INFO => LogLevel::Info,
+            tracing::Level::WARN => LogLevel::Warn,
+            tracing::Level::ERROR => LogLevel::Error,
+        }
+    }
+}
+
+/// Why the webview closed.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum CloseReason {
+    /// The user closed the window.
+    UserClosed,
+    /// The client sent a `close` request.
+    ClientRequested,
+    /// The webview failed to start or its web content process was killed, e.g. for exceeding its memory limit.
+    Error,
+    /// The web content process crashed.
+    Crashed,
+    /// The process was asked to stop with `SIGTERM`, `SIGINT` or `SIGHUP`.
+    ///
+    /// Platform-specific:
+    /// - Windows: Unsupported, the process is ended without a `closed` notification.
+    Signal,
+}
+
+/// The kind of permission a page is requesting.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum PermissionKind {
+    Camera,
+    Microphone,
+    CameraAndMicrophone,
+    Geolocation,
+    Notifications,
+    Other,
+}
+
+/// The reason the webview was reloaded.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReloadReason {
+    /// A file in the watched directory changed.
+    FileChange,
+}
+
+/// Explicit requests from the client to the webview.
+#[derive(JsonSchema, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "$type")]
+pub enum Request {
+    GetVersion {
+        /// The id of the request.
+        id: i64,
+    },
+    Eval {
+        /// The id of the request.
+        id: i64,
+        /// The javascript to evaluate.
+        js: String,
+        /// A CSS selector for an iframe to evaluate the javascript in. Defaults to the top frame.
+        ///
+        /// The frame must be same-origin with the page, cross-origin frames return an error.
+        #[serde(default)]
+        frame: Option<String>,
+        /// When true, the javascript's value is awaited and responded with as a base64 `string` result, e.g. to get image or file data out of the page. It must be an `ArrayBuffer`, a typed array, a `DataView` or a `Blob`, other values return an error. Default is false.
+        ///
+        /// Values are limited to 64 MiB. The data is sent from the page in one message and the response is written on one line, so it's held in memory several times over and other messages wait while it's written. Larger data can be read in parts by evaluating e.g. `blob.slice(start, end)` in several requests.
+        #[serde(default)]
+        binary: bool,
+    },
+    SetTitle {
+        /// The id of the request.
+        id: i64,
+        /// The title to set.
+        title: String,
+    },
+    GetTitle {
+        /// The id of the request.
+        id: i64,
+        /// Whether to get the native window title or the page's `document.title`. Default is `window`.
+        #[serde(default)]
+        source: TitleSource,
+    },
+    SetVisibility {
+        /// The id of the request.
+        id: i64,
+        /// Whether the window should be visible or hidden.
+        visible: bool,
+    },
+    IsVisible {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Shows or hides the web content without hiding the window, e.g. to show native content in its place.
+    SetWebviewVisible {
+        /// The id of the request.
+        id: i64,
+        /// Whether the web content should be visible or hidden.
+        visible: bool,
+    },
+    /// Responds with a `boolean` result of whether the web content is visible, as set by `setWebviewVisible`.
+    IsWebviewVisible {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Brings the window in front of other windows without taking keyboard focus from the window the user is typing in, e.g. for notifications.
+    ///
+    /// Platform-specific:
+    /// - Linux: Wayland doesn't let windows raise themselves, so nothing happens there.
+    /// - macOS: The window is focused as well.
+    Raise {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Moves keyboard focus to the web content, so key presses go to the page rather than the window.
+    FocusWebview {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with a `boolean` result of whether the web content has keyboard focus.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported.
+    IsWebviewFocused {
+        /// The id of the request.
+        id: i64,
+    },
+    OpenDevTools {
+        /// The id of the request.
+        id: i64,
+    },
+    CloseDevTools {
+        /// The id of the request.
+        id: i64,
+    },
+    IsDevToolsOpen {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with a `json` result describing the window, for debugging and bug reports: its `title`, `innerSize` and `outerSize`, `position`, `scaleFactor`, whether it's `visible`, `focused`, `maximized`, `minimized`, `fullscreen`, `alwaysOnTop`, `decorated`, `resizable` and `closable`, and the webview's `url`, whether it's `webviewVisible` and whether the page is `loading`.
+    ///
+    /// Sizes and positions are in logical pixels. Values the platform can't report are `null`.
+    DumpWindowState {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Gets the size of the window. Until the window has been mapped, which may not have happened yet right after `started`, the size it was created or last set with is returned instead of 0x0.
+    GetSize {
+        /// The id of the request.
+        id: i64,
+        /// Whether to include the title bar and borders in the size measurement.
+        #[serde(default)]
+        include_decorations: Option<bool>,
+    },
+    SetSize {
+        /// The id of the request.
+        id: i64,
+        /// The size to set.
+        size: Size,
+    },
+    /// Replaces the `aspectRatio` and `resizeIncrements` constraints and applies them to the current size. Leaving either out removes it.
+    SetResizeConstraints {
+        /// The id of the request.
+        id: i64,
+        /// The ratio of the inner width to the height to keep.
+        #[serde(default)]
+        aspect_ratio: Option<f64>,
+        /// The size to snap the inner size to multiples of.
+        #[serde(default)]
+        resize_increments: Option<Size>,
+    },
+    Fullscreen {
+        /// The id of the request.
+        id: i64,
+        /// Whether to enter fullscreen mode.
+        /// If left unspecified, the window will enter fullscreen mode if it is not already in fullscreen mode
+        /// or exit fullscreen mode if it is currently in fullscreen mode.
         fullscreen: Option<bool>,
     },
     Maximize {
@@ -250,6 +1172,18 @@ pub enum Request {
         /// or restored if it was previously minimized.
         minimized: Option<bool>,
     },
+    IsFullscreen {
+        /// The id of the request.
+        id: i64,
+    },
+    IsMaximized {
+        /// The id of the request.
+        id: i64,
+    },
+    IsMinimized {
+        /// The id of the request.
+        id: i64,
+    },
     LoadHtml {
         /// The id of the request.
         id: i64,
@@ -258,6 +1192,12 @@ pub enum Request {
         /// What to set as the origin of the webview when loading html.
         /// If not specified, the origin will be set to the value of the `origin` field when the webview was created.
         origin: Option<String>,
+        /// The charset the html is encoded in and declared with in its `Content-Type`, see the `html` load option. Default is `utf-8`.
+        #[serde(default = "default_charset")]
+        charset: String,
+        /// When true, the current page is replaced without adding an entry to the history, like `location.replace`. Default is false.
+        #[serde(default)]
+        replace: bool,
     },
     LoadUrl {
         /// The id of the request.
@@ -266,729 +1206,6951 @@ pub enum Request {
         url: String,
         /// Optional headers to send with the request.
         headers: Option<HashMap<String, String>>,
+        /// The `Referer` header to send with the request, overriding the `referer` option and any `Referer` in `headers`.
+        referer: Option<String>,
+        /// When true, the current page is replaced without adding an entry to the history, like `location.replace`. Default is false.
+        ///
+        /// The navigation is started by the page itself, so it can't send headers, including `defaultHeaders` and `referer`, and goes through the page's own handlers such as `beforeunload`.
+        #[serde(default)]
+        replace: bool,
     },
-}
-
-/// Responses from the webview to the client.
-#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "$type")]
-pub enum Response {
-    Ack { id: i64 },
-    Result { id: i64, result: ResultType },
-    Err { id: i64, message: String },
-}
-
-/// Types that can be returned from webview results.
-#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-#[serde(tag = "$type", content = "value")]
-#[allow(dead_code)]
-pub enum ResultType {
-    String(String),
-    Boolean(bool),
-    Float(f64),
-    Size(SizeWithScale),
-}
-
-impl From<String> for ResultType {
-    fn from(value: String) -> Self {
-        ResultType::String(value)
-    }
-}
-
-impl From<bool> for ResultType {
-    fn from(value: bool) -> Self {
-        ResultType::Boolean(value)
-    }
-}
-
-/// Incrementally parses JSON input from a reader and sends the parsed requests to a sender.
-///
-/// This is used in the main program to read JSON input from stdin and send it to the webview
-/// event loop.
-fn process_input<R: Read + std::marker::Send + 'static>(
-    reader: BufReader<R>,
-    sender: Sender<Request>,
-) {
-    std::thread::spawn(move || {
-        let feeder = BufReaderJsonFeeder::new(reader);
-        let mut parser = JsonParser::new_with_options(
-            feeder,
-            JsonParserOptionsBuilder::default()
-                .with_streaming(true)
-                .build(),
-        );
-
-        let mut json_string = String::new();
-        let mut depth = 0;
-
-        while let Some(event) = parser.next_event().unwrap() {
-            match event {
-                JsonEvent::NeedMoreInput => parser.feeder.fill_buf().unwrap(),
-                JsonEvent::StartObject => {
-                    depth += 1;
-                    json_string.push('{');
-                }
-                JsonEvent::EndObject => {
-                    depth -= 1;
-                    json_string.push('}');
-
-                    // If we're back at depth 0, we have a complete JSON object
-                    if depth == 0 {
-                        match serde_json::from_str::<Request>(&json_string) {
-                            Ok(request) => {
-                                debug!(request = ?request, "Received request from client");
-                                sender.send(request).unwrap()
-                            }
-                            Err(e) => error!("Failed to deserialize request: {:?}", e),
-                        }
-                        json_string.clear();
-                    }
-                }
-                JsonEvent::StartArray => {
-                    depth += 1;
-                    json_string.push('[');
-                }
-                JsonEvent::EndArray => {
-                    depth -= 1;
-                    json_string.push(']');
-                }
-                JsonEvent::FieldName => {
-                    if json_string.ends_with('{') {
-                        json_string.push('"');
-                    } else {
-                        json_string.push_str(",\"");
-                    }
-                    json_string.push_str(parser.current_str().unwrap());
+    LoadDirectory {
+        /// The id of the request.
+        id: i64,
+        /// Path to a local directory whose files will be served to the webview.
+        path: String,
+        /// The file within the directory to load first. Default is `index.html`.
+        #[serde(default = "default_entry")]
+        entry: String,
+    },
+    PermissionDecision {
+        /// The id of the request.
+        id: i64,
+        /// The id from the `permissionRequested` notification being answered.
+        request_id: u64,
+        /// Whether to grant the permission.
+        allow: bool,
+    },
+    ProtocolResponse {
+        /// The id of the request.
+        id: i64,
+        /// The id from the `protocolRequest` notification being answered.
+        request_id: u64,
+        /// The HTTP status code.
+        status: u16,
+        /// The response headers.
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// The base64 encoded response body.
+        #[serde(default)]
+        body: String,
+    },
+    Close {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Asks for the window to be repainted, for platforms that otherwise don't repaint an idle window until an OS event arrives.
+    ///
+    /// Done automatically after `setSize` and `setVisibility`.
+    RequestRedraw {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Changes the `smoothScrolling` and `overscroll` options for the current page and later ones.
+    SetScrollBehavior {
+        /// The id of the request.
+        id: i64,
+        /// Whether scrolling is animated. It's left as it is if not set.
+        smooth_scrolling: Option<bool>,
+        /// Whether scrolling past the edge of the page bounces. It's left as it is if not set.
+        overscroll: Option<bool>,
+    },
+    /// Switches between following the system's theme and pinning the window to one, like a "use system theme" setting, see the `theme` option.
+    ///
+    /// Pinning without a `theme` keeps the window's current theme.
+    SetFollowSystemTheme {
+        /// The id of the request.
+        id: i64,
+        /// Whether the window follows the system's theme and sends `themeChanged` notifications.
+        follow: bool,
+        /// The theme to pin the window to when `follow` is false.
+        theme: Option<Theme>,
+    },
+    /// Shows or hides the page's scrollbars for the current page and later ones, see the `scrollbarsVisible` option.
+    SetScrollbarsVisible {
+        /// The id of the request.
+        id: i64,
+        /// Whether the scrollbars are shown.
+        visible: bool,
+    },
+    /// Answers a message the page sent with `window.webviewRequest`, resolving or rejecting the promise it returned.
+    ///
+    /// Answers to messages from a page that has since navigated away are dropped.
+    RespondToPage {
+        /// The id of the request.
+        id: i64,
+        /// The `rpc_id` of the `ipc` notification being answered.
+        rpc_id: u64,
+        /// The value the page's promise resolves with.
+        #[serde(default)]
+        data: serde_json::Value,
+        /// When set, the page's promise is rejected with an error with this message instead.
+        error: Option<String>,
+    },
+    /// Turns the `emulateTouch` option on or off for the current page and later ones.
+    SetTouchEmulation {
+        /// The id of the request.
+        id: i64,
+        /// Whether mouse input is turned into touch events.
+        enabled: bool,
+    },
+    /// Changes the `dragRegionHeight` option for the current page and later ones.
+    SetDragRegionHeight {
+        /// The id of the request.
+        id: i64,
+        /// The height of the drag region in logical pixels, 0 removes it.
+        height: f64,
+    },
+    /// Responds with a `float` result holding the current `dragRegionHeight`.
+    GetDragRegionHeight {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Scales the page's text by a factor without scaling images or the rest of the layout, e.g. `1.5` for larger text.
+    ///
+    /// Platform-specific:
+    /// - Linux: Text zoom replaces the zoom the user set with ctrl + scroll, which also only scales text afterwards. A factor of `1` restores full page zoom.
+    /// - macOS / Windows: Unsupported, the platforms only zoom whole pages.
+    SetTextZoom {
+        /// The id of the request.
+        id: i64,
+        /// The factor to scale text by, `1` is the default size.
+        factor: f64,
+    },
+    /// Moves, shows or hides the close, minimize and zoom buttons in the title bar.
+    ///
+    /// Platform-specific:
+    /// - **Linux / Windows**: Unsupported.
+    SetTrafficLights {
+        /// The id of the request.
+        id: i64,
+        /// The position of the buttons relative to the top-left corner of the window. Unchanged if not specified.
+        inset: Option<Position>,
+        /// Whether the buttons are shown. Unchanged if not specified.
+        visible: Option<bool>,
+    },
+    /// Moves the window to the center of a monitor, optionally resizing it to fill the monitor's work area.
+    MoveToMonitor {
+        /// The id of the request.
+        id: i64,
+        /// The name of the monitor, as reported by the platform.
+        monitor: String,
+        /// When true, the window is resized to fill the monitor's work area, which excludes the taskbar or dock.
+        ///
+        /// Platform-specific:
+        /// - **macOS / Windows**: The whole monitor is filled, its work area isn't known.
+        #[serde(default)]
+        maximize_to_work_area: bool,
+    },
+    SetAlwaysOnBottom {
+        /// The id of the request.
+        id: i64,
+        /// Whether the window should be kept below all other windows.
+        always_on_bottom: bool,
+    },
+    /// Makes the window click-through except for the given regions, so clicks reach the windows behind it everywhere else. Useful for overlays with a few buttons.
+    ///
+    /// The cursor position is polled while regions are set, since the page stops receiving mouse events once they're ignored.
+    ///
+    /// Platform-specific:
+    /// - **Linux (Wayland)**: Unsupported, the cursor position can't be read.
+    SetIgnoreCursorEventsRegion {
+        /// The id of the request.
+        id: i64,
+        /// The regions that stay interactive, in logical pixels relative to the top-left corner of the page. An empty list makes the whole window interactive again.
+        interactive: Vec<Rect>,
+    },
+    /// Moves the mouse pointer to a point in the window, e.g. for UI tests or games.
+    ///
+    /// Platform-specific:
+    /// - **Linux (Wayland)**: Unsupported, Wayland doesn't let applications move the pointer.
+    SetCursorPosition {
+        /// The id of the request.
+        id: i64,
+        /// The x coordinate in logical pixels from the left of the window's content.
+        x: f64,
+        /// The y coordinate in logical pixels from the top of the window's content.
+        y: f64,
+    },
+    /// Evaluates javascript once the page, including any load that's in progress, has finished loading.
+    ///
+    /// Responds with the result as a `json` value, promises are awaited.
+    WhenReady {
+        /// The id of the request.
+        id: i64,
+        /// The javascript to evaluate.
+        js: String,
+        /// How long to wait for the page to load and the script to finish before responding with an error. Default is 10000.
+        #[serde(default = "default_when_ready_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Runs a script on the current page and on every page loaded after it, until it's removed with `removeScript`.
+    ///
+    /// The script is identified by the id of this request.
+    ///
+    /// Platform-specific:
+    /// - Linux: On later loads the script runs before the page's own scripts, like `initializationScript`.
+    /// - macOS / Windows: On later loads the script runs once the page has finished loading.
+    AddScript {
+        /// The id of the request.
+        id: i64,
+        /// The javascript to run.
+        script: String,
+    },
+    /// Stops a script added with `addScript` from running on future page loads. Its effects on the current page remain.
+    RemoveScript {
+        /// The id of the request.
+        id: i64,
+        /// The id of the `addScript` request that added the script.
+        script_id: i64,
+    },
+    /// Captures the element matching a CSS selector as a base64 encoded PNG.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported.
+    CaptureElement {
+        /// The id of the request.
+        id: i64,
+        /// A CSS selector for the element to capture.
+        selector: String,
+    },
+    /// Searches the page for text, highlighting the next match. Repeating the search moves through the matches, and the result is reported with a `findResult` notification.
+    ///
+    /// The active match is counted from the searches made, so it can be off after the user selects text in the page.
+    FindInPage {
+        /// The id of the request.
+        id: i64,
+        /// The text to search for. An empty query stops the search.
+        query: String,
+        /// Whether to move to the next match rather than the previous one. Default is true.
+        #[serde(default = "default_true")]
+        forward: bool,
+        /// Whether the search is case sensitive. Default is false.
+        #[serde(default)]
+        match_case: bool,
+    },
+    /// Stops searching the page, clearing the highlighted match.
+    StopFind {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Runs a clipboard action against the page's focused element or selection with `document.execCommand`, responding with a `boolean` result of whether the page ran it.
+    ///
+    /// `execCommand` is deprecated, but it's still the only way to trigger these actions from a script. The page can't read the clipboard unless the `clipboard` option is enabled, so `paste` fails without it.
+    ///
+    /// Platform-specific:
+    /// - macOS: `paste` isn't supported.
+    ClipboardAction {
+        /// The id of the request.
+        id: i64,
+        /// The action to run.
+        action: ClipboardAction,
+    },
+    /// Responds with a `string` result of the text on the system clipboard, read directly rather than through the page. It's empty when the clipboard doesn't hold text.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported.
+    ReadClipboard {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Puts text on the system clipboard directly rather than through the page.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported.
+    WriteClipboard {
+        /// The id of the request.
+        id: i64,
+        /// The text to put on the clipboard.
+        text: String,
+    },
+    /// Responds with a `string` result of the text selected in the page, including text selected in a focused input or textarea. It's empty when nothing is selected.
+    GetSelection {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Clears the page's text selection.
+    ClearSelection {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Clicks the element at a point in the page, responding with a `boolean` result of whether there was an element there to click.
+    ///
+    /// The click is dispatched as the pointer and mouse events a real one would fire, which the page can tell apart from a real click by their `isTrusted` property.
+    Click {
+        /// The id of the request.
+        id: i64,
+        /// The x coordinate to click at, in CSS pixels from the left of the viewport.
+        x: f64,
+        /// The y coordinate to click at, in CSS pixels from the top of the viewport.
+        y: f64,
+    },
+    /// Negotiates the protocol version, responding with a `json` result holding the `min` and `max` protocol versions the webview supports along with whether the client's version is among them as `supported`.
+    Hello {
+        /// The id of the request.
+        id: i64,
+        /// The version of the protocol the client speaks.
+        client_protocol_version: u32,
+    },
+    /// Responds with a `json` result listing the frames of the page, in document order. Each has a `selector` that can be passed as the `frame` of an `eval` request, its `name`, its `url` and whether it's `sameOrigin` with the page.
+    ///
+    /// Only frames in the page's own document are listed, not frames nested inside them. The `url` of a cross-origin frame is its `src`, since the page can't see where it navigated to since.
+    GetFrames {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with a `json` result holding the current page's load timings in milliseconds: `timeToFirstByte`, `domContentLoaded`, `load`, `firstPaint` and `firstContentfulPaint`, which are `null` if they haven't happened or aren't measured, along with the raw `navigation` and `paint` performance entries.
+    GetPerformanceTiming {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with a `json` result holding the urls the most recent navigation went through, in order, including any redirects it followed.
+    GetLastNavigationChain {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Reads the current page's `localStorage` and `sessionStorage`, responding with a `json` result holding the page's `origin` and a `localStorage` and `sessionStorage` map, which can be passed to `importStorage` later.
+    ExportStorage {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Writes storage exported with `exportStorage` back into its origin.
+    ///
+    /// If the current page is on that origin it's written right away, and it's written before the page's scripts run the next time a page on that origin loads otherwise. It's only written once per tab, and the script writing it can be removed with `removeScript` using this request's id.
+    ImportStorage {
+        /// The id of the request.
+        id: i64,
+        /// The storage to write.
+        data: StorageSnapshot,
+    },
+    /// Replaces the page's web content process with a new one and reloads the page, e.g. to recover from a page that stopped responding. Scripts and options are kept, and a `webviewRecreated` notification is sent once the reload has started.
+    ///
+    /// Requests waiting on the old page, like `whenReady` and `exportStorage`, fail.
+    ///
+    /// Platform-specific:
+    /// - macOS / Windows: Unsupported.
+    RecreateWebview {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with `pong` from the event loop without touching the window, to check it's responsive or measure round-trip latency.
+    Ping {
+        /// The id of the request.
+        id: i64,
+    },
+    /// Responds with `payload` unchanged as a `json` result, for testing a client's framing and correlation without side effects.
+    Echo {
+        /// The id of the request.
+        id: i64,
+        /// Any value to send back.
+        payload: serde_json::Value,
+    },
+    /// Handles several requests in order without anything else happening in between, responding with a `batch` result holding the response to each of them.
+    ///
+    /// Requests that are answered asynchronously (such as `whenReady`, `captureElement` or `eval` in a frame), `close` and nested batches can't be batched and are answered with an error without being run.
+    Batch {
+        /// The id of the request.
+        id: i64,
+        /// The requests to handle.
+        // Described as plain values since the schema code generators can't handle recursive types.
+        #[schemars(with = "Vec<serde_json::Value>")]
+        requests: Vec<Request>,
+    },
+}
+
+fn default_when_ready_timeout_ms() -> u64 {
+    10000
+}
+
+impl Request {
+    /// The id of the request, used to correlate it with its response.
+    pub fn id(&self) -> i64 {
+        match self {
+            Request::GetVersion { id }
+            | Request::Eval { id, .. }
+            | Request::SetTitle { id, .. }
+            | Request::GetTitle { id, .. }
+            | Request::SetVisibility { id, .. }
+            | Request::IsVisible { id }
+            | Request::SetWebviewVisible { id, .. }
+            | Request::IsWebviewVisible { id }
+            | Request::Raise { id }
+            | Request::FocusWebview { id }
+            | Request::IsWebviewFocused { id }
+            | Request::OpenDevTools { id }
+            | Request::CloseDevTools { id }
+            | Request::IsDevToolsOpen { id }
+            | Request::DumpWindowState { id }
+            | Request::GetSize { id, .. }
+            | Request::SetSize { id, .. }
+            | Request::SetResizeConstraints { id, .. }
+            | Request::Fullscreen { id, .. }
+            | Request::Maximize { id, .. }
+            | Request::Minimize { id, .. }
+            | Request::IsFullscreen { id }
+            | Request::IsMaximized { id }
+            | Request::IsMinimized { id }
+            | Request::LoadHtml { id, .. }
+            | Request::LoadUrl { id, .. }
+            | Request::LoadDirectory { id, .. }
+            | Request::PermissionDecision { id, .. }
+            | Request::ProtocolResponse { id, .. }
+            | Request::Close { id }
+            | Request::RequestRedraw { id }
+            | Request::SetTextZoom { id, .. }
+            | Request::SetScrollBehavior { id, .. }
+            | Request::SetScrollbarsVisible { id, .. }
+            | Request::SetFollowSystemTheme { id, .. }
+            | Request::SetDragRegionHeight { id, .. }
+            | Request::SetTouchEmulation { id, .. }
+            | Request::RespondToPage { id, .. }
+            | Request::GetDragRegionHeight { id }
+            | Request::SetTrafficLights { id, .. }
+            | Request::MoveToMonitor { id, .. }
+            | Request::SetAlwaysOnBottom { id, .. }
+            | Request::SetIgnoreCursorEventsRegion { id, .. }
+            | Request::SetCursorPosition { id, .. }
+            | Request::WhenReady { id, .. }
+            | Request::AddScript { id, .. }
+            | Request::RemoveScript { id, .. }
+            | Request::CaptureElement { id, .. }
+            | Request::FindInPage { id, .. }
+            | Request::StopFind { id }
+            | Request::ClipboardAction { id, .. }
+            | Request::ReadClipboard { id }
+            | Request::WriteClipboard { id, .. }
+            | Request::GetSelection { id }
+            | Request::ClearSelection { id }
+            | Request::Click { id, .. }
+            | Request::Hello { id, .. }
+            | Request::GetPerformanceTiming { id }
+            | Request::GetFrames { id }
+            | Request::GetLastNavigationChain { id }
+            | Request::ExportStorage { id }
+            | Request::ImportStorage { id, .. }
+            | Request::RecreateWebview { id }
+            | Request::Ping { id }
+            | Request::Echo { id, .. }
+            | Request::Batch { id, .. } => *id,
+        }
+    }
+
+    /// Whether the request can be handled as part of a batch, which needs its response right away.
+    fn is_batchable(&self) -> bool {
+        !matches!(
+            self,
+            Request::Eval { frame: Some(_), .. }
+                | Request::Eval { binary: true, .. }
+                | Request::GetTitle {
+                    source: TitleSource::Document,
+                    ..
+                }
+                | Request::WhenReady { .. }
+                | Request::CaptureElement { .. }
+                | Request::FindInPage { .. }
+                | Request::ClipboardAction { .. }
+                | Request::ReadClipboard { .. }
+                | Request::GetSelection { .. }
+                | Request::Click { .. }
+                | Request::GetPerformanceTiming { .. }
+                | Request::GetFrames { .. }
+                | Request::ExportStorage { .. }
+                | Request::ImportStorage { .. }
+                | Request::Close { .. }
+                | Request::Batch { .. }
+        )
+    }
+}
+
+/// The kind of window, as set with the `windowType` option.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum WindowType {
+    /// A regular application window.
+    Normal,
+    /// A helper window like a palette or toolbox.
+    Utility,
+    /// A dialog window.
+    Dialog,
+}
+
+/// Which media can be played without user interaction.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoplayPolicy {
+    /// All media can be autoplayed.
+    Allow,
+    /// Only muted media can be autoplayed.
+    AllowMuted,
+    /// No media can be autoplayed.
+    Deny,
+}
+
+/// How much of a page's url is sent as the `Referer` of its requests, see
+/// <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Referrer-Policy>.
+#[derive(JsonSchema, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    /// The policy's name as used in the `Referrer-Policy` header and `<meta name="referrer">`.
+    fn as_str(self) -> &'static str {
+        match self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        }
+    }
+}
+
+/// A clipboard action run by a `clipboardAction` request.
+#[derive(JsonSchema, Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ClipboardAction {
+    Copy,
+    Cut,
+    Paste,
+    SelectAll,
+}
+
+impl ClipboardAction {
+    /// The `execCommand` command that runs the action.
+    fn command(self) -> &'static str {
+        match self {
+            ClipboardAction::Copy => "copy",
+            ClipboardAction::Cut => "cut",
+            ClipboardAction::Paste => "paste",
+            ClipboardAction::SelectAll => "selectAll",
+        }
+    }
+}
+
+/// A page's web storage, as exported by `exportStorage`.
+#[derive(JsonSchema, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageSnapshot {
+    /// The origin the storage belongs to, e.g. `https://example.com`.
+    origin: String,
+    /// The entries in `localStorage`.
+    #[serde(default)]
+    local_storage: HashMap<String, String>,
+    /// The entries in `sessionStorage`.
+    #[serde(default)]
+    session_storage: HashMap<String, String>,
+}
+
+/// Where to read a title from.
+#[derive(JsonSchema, Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TitleSource {
+    /// The native window title.
+    #[default]
+    Window,
+    /// The page's `document.title`, which can differ from the window title.
+    Document,
+}
+
+/// Responses from the webview to the client.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "$type")]
+pub enum Response {
+    Ack { id: i64 },
+    Result { id: i64, result: ResultType },
+    Err { id: i64, message: String },
+}
+
+impl Response {
+    /// The id of the request this is a response to.
+    pub fn id(&self) -> i64 {
+        match self {
+            Response::Ack { id } | Response::Result { id, .. } | Response::Err { id, .. } => *id,
+        }
+    }
+}
+
+/// Types that can be returned from webview results.
+#[derive(JsonSchema, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "$type", content = "value")]
+#[allow(dead_code)]
+pub enum ResultType {
+    String(String),
+    Boolean(bool),
+    Float(f64),
+    Size(SizeWithScale),
+    /// The responses to the requests in a batch, in order.
+    Batch(#[schemars(with = "Vec<serde_json::Value>")] Vec<Response>),
+    /// Any structured value, such as a list or a map.
+    Json(serde_json::Value),
+}
+
+impl From<String> for ResultType {
+    fn from(value: String) -> Self {
+        ResultType::String(value)
+    }
+}
+
+impl From<serde_json::Value> for ResultType {
+    fn from(value: serde_json::Value) -> Self {
+        ResultType::Json(value)
+    }
+}
+
+impl From<bool> for ResultType {
+    fn from(value: bool) -> Self {
+        ResultType::Boolean(value)
+    }
+}
+
+/// Determines which kind of permission a WebKitGTK permission request is for.
+#[cfg(target_os = "linux")]
+fn permission_kind(request: &webkit2gtk::PermissionRequest) -> PermissionKind {
+    use webkit2gtk::glib::prelude::{Cast, ObjectExt};
+    use webkit2gtk::UserMediaPermissionRequestExt;
+
+    if let Some(media) = request.downcast_ref::<webkit2gtk::UserMediaPermissionRequest>() {
+        return match (media.is_for_video_device(), media.is_for_audio_device()) {
+            (true, true) => PermissionKind::CameraAndMicrophone,
+            (true, false) => PermissionKind::Camera,
+            (false, true) => PermissionKind::Microphone,
+            (false, false) => PermissionKind::Other,
+        };
+    }
+    if request.is::<webkit2gtk::GeolocationPermissionRequest>() {
+        PermissionKind::Geolocation
+    } else if request.is::<webkit2gtk::NotificationPermissionRequest>() {
+        PermissionKind::Notifications
+    } else {
+        PermissionKind::Other
+    }
+}
+
+/// The bounds of a monitor's work area in logical pixels, which excludes the taskbar or dock
+/// where the platform reports it and is the whole monitor elsewhere.
+fn monitor_work_area(monitor: &tao::monitor::MonitorHandle) -> Rect {
+    let scale_factor = monitor.scale_factor();
+    let position = monitor.position().to_logical::<f64>(scale_factor);
+    #[cfg(target_os = "linux")]
+    {
+        use gtk::gdk::prelude::MonitorExt;
+        // GDK works in logical pixels.
+        let work_area = gtk::gdk::Display::default()
+            .and_then(|display| display.monitor_at_point(position.x as i32, position.y as i32))
+            .map(|monitor| monitor.workarea());
+        if let Some(work_area) = work_area {
+            return Rect {
+                x: work_area.x() as f64,
+                y: work_area.y() as f64,
+                width: work_area.width() as f64,
+                height: work_area.height() as f64,
+            };
+        }
+    }
+    let size = monitor.size().to_logical::<f64>(scale_factor);
+    Rect {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    }
+}
+
+/// Some platforms report a size of 0x0 until the window is mapped, in which case the size it was
+/// created or last set with is used instead, if it's known.
+fn unmapped_size(size: (f64, f64), unmapped_inner_size: Option<(f64, f64)>) -> (f64, f64) {
+    match unmapped_inner_size {
+        Some(unmapped) if size.0 == 0.0 || size.1 == 0.0 => unmapped,
+        _ => size,
+    }
+}
+
+/// Describes the window and webview for a `dumpWindowState` request.
+fn window_state(
+    window: &tao::window::Window,
+    webview: &wry::WebView,
+    webview_visible: bool,
+    page_loading: bool,
+) -> serde_json::Value {
+    let scale_factor = window.scale_factor();
+    let size = |size: dpi::PhysicalSize<u32>| {
+        let size = size.to_logical::<f64>(scale_factor);
+        serde_json::json!({ "width": size.width, "height": size.height })
+    };
+    let position = window.outer_position().ok().map(|position| {
+        let position = position.to_logical::<f64>(scale_factor);
+        serde_json::json!({ "x": position.x, "y": position.y })
+    });
+    serde_json::json!({
+        "title": window.title(),
+        "innerSize": size(window.inner_size()),
+        "outerSize": size(window.outer_size()),
+        "position": position,
+        "scaleFactor": scale_factor,
+        "visible": window.is_visible(),
+        "focused": window.is_focused(),
+        "maximized": window.is_maximized(),
+        "minimized": window.is_minimized(),
+        "fullscreen": window.fullscreen().is_some(),
+        "alwaysOnTop": window.is_always_on_top(),
+        "decorated": window.is_decorated(),
+        "resizable": window.is_resizable(),
+        "closable": window.is_closable(),
+        "url": webview.url().ok(),
+        "webviewVisible": webview_visible,
+        "loading": page_loading,
+    })
+}
+
+/// Reads the message a panic was started with.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// How often the cursor is checked against the interactive regions, about once a frame.
+const CURSOR_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Whether the cursor is within one of `regions`, given relative to the window's inner position.
+fn cursor_in_regions(window: &tao::window::Window, regions: &[Rect]) -> Option<bool> {
+    let cursor = window.cursor_position().ok()?;
+    let origin = window.inner_position().ok()?;
+    let scale_factor = window.scale_factor();
+    let x = (cursor.x - origin.x as f64) / scale_factor;
+    let y = (cursor.y - origin.y as f64) / scale_factor;
+    Some(regions.iter().any(|region| region.contains(x, y)))
+}
+
+/// Corrects a window size to the nearest one that's a multiple of `increments` and has the given
+/// aspect ratio. The height follows the width when both are set.
+fn constrain_size(
+    (mut width, mut height): (f64, f64),
+    increments: Option<(f64, f64)>,
+    aspect_ratio: Option<f64>,
+) -> (f64, f64) {
+    if let Some((width_increment, height_increment)) = increments {
+        if width_increment > 0.0 {
+            width = (width / width_increment).round().max(1.0) * width_increment;
+        }
+        if height_increment > 0.0 {
+            height = (height / height_increment).round().max(1.0) * height_increment;
+        }
+    }
+    if let Some(aspect_ratio) = aspect_ratio.filter(|ratio| *ratio > 0.0) {
+        height = (width / aspect_ratio).round();
+    }
+    (width, height)
+}
+
+/// Computes the outer position of a window of `size` placed next to `anchor`, clamped to the
+/// `monitor` bounds.
+fn anchored_position(anchor: &Anchor, size: (f64, f64), monitor: &Rect) -> (f64, f64) {
+    let (width, height) = size;
+    let rect = &anchor.rect;
+    let center_x = rect.x + (rect.width - width) / 2.0;
+    let center_y = rect.y + (rect.height - height) / 2.0;
+    let (x, y) = match anchor.placement {
+        Placement::Above => (center_x, rect.y - height - anchor.offset),
+        Placement::Below => (center_x, rect.y + rect.height + anchor.offset),
+        Placement::Left => (rect.x - width - anchor.offset, center_y),
+        Placement::Right => (rect.x + rect.width + anchor.offset, center_y),
+    };
+    // Windows larger than the monitor are aligned to its top left corner.
+    let clamp = |value: f64, min: f64, max: f64| value.min(max).max(min);
+    (
+        clamp(x, monitor.x, monitor.x + monitor.width - width),
+        clamp(y, monitor.y, monitor.y + monitor.height - height),
+    )
+}
+
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+enum UserEvent {
+    /// A file in the watched directory changed.
+    #[cfg_attr(not(feature = "watch"), allow(dead_code))]
+    FileChanged,
+    /// A script injected by the webview posted a message back from the page.
+    Page(PageMessage),
+    /// The page's `document.title` changed.
+    DocumentTitleChanged(String),
+    /// The page started loading.
+    PageLoadStarted,
+    /// The page finished loading.
+    PageLoadFinished,
+    /// The page navigated to a url without the default headers.
+    NavigateWithHeaders(String),
+    /// The web content process terminated unexpectedly.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    WebProcessTerminated(CloseReason),
+    /// The process received a termination signal.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Signal(i32),
+    /// The system is running low on memory.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    MemoryPressure(MemoryPressureLevel),
+}
+
+/// Messages posted over IPC by scripts the webview injects into the page.
+///
+/// They're wrapped in an object with a `__webview` key and the `page_token`, so they can be told
+/// apart from the page's own IPC messages. The page can still post them itself, so they're
+/// untrusted input: they're only acted on when the webview is set up to send them.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", tag = "$type")]
+enum PageMessage {
+    /// A correlated script finished, `result` is its JSON serialized return value.
+    EvalResult {
+        id: i64,
+        #[serde(default)]
+        result: serde_json::Value,
+    },
+    /// A correlated script threw.
+    EvalError { id: i64, message: String },
+    /// The page painted for the first time.
+    FirstPaint,
+    /// An element of the page entered or left fullscreen.
+    FullscreenChanged { fullscreen: bool },
+    /// The mouse was pressed in the drag region.
+    StartDrag,
+    /// The page violated its content security policy.
+    CspViolation {
+        directive: String,
+        blocked_uri: String,
+        source_file: Option<String>,
+        line_number: Option<u32>,
+        column_number: Option<u32>,
+        sample: Option<String>,
+        enforced: bool,
+    },
+}
+
+impl PageMessage {
+    /// Parses an IPC message body, returning `None` if it wasn't sent by an injected script.
+    fn parse(body: &str) -> Option<PageMessage> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            token: String,
+            #[serde(rename = "__webview")]
+            message: PageMessage,
+        }
+        serde_json::from_str::<Envelope>(body)
+            .ok()
+            .filter(|envelope| envelope.token == page_token())
+            .map(|envelope| envelope.message)
+    }
+}
+
+/// A random token the injected scripts add to their IPC messages, so that a page posting messages
+/// that happen to look like them isn't mistaken for the webview.
+fn page_token() -> &'static str {
+    static TOKEN: OnceLock<String> = OnceLock::new();
+    TOKEN.get_or_init(|| {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).expect("Failed to generate a random token");
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    })
+}
+
+/// Builds a javascript expression that posts the `PageMessage` that `message` evaluates to.
+fn post_page_message(message: &str) -> String {
+    format!(
+        r#"window.ipc.postMessage(JSON.stringify({{ token: "{}", __webview: {} }}))"#,
+        page_token(),
+        message
+    )
+}
+
+/// Wraps the body of an async javascript function so that its outcome is posted back over IPC as a
+/// `PageMessage` correlated by `id`.
+fn correlated_script(id: i64, body: &str) -> String {
+    format!(
+        r#"(async () => {{
+  const post = (message) => {post};
+  try {{
+    const result = await (async () => {{
+{body}
+    }})();
+    post({{ $type: "evalResult", id: {id}, result: result === undefined ? null : result }});
+  }} catch (error) {{
+    post({{ $type: "evalError", id: {id}, message: String(error && error.message || error) }});
+  }}
+}})();"#,
+        post = post_page_message("message")
+    )
+}
+
+/// Posts a `FirstPaint` page message once the top frame has rendered its first frame.
+fn first_paint_script() -> String {
+    format!(
+        r#"if (window.top === window) {{
+  const ping = () => requestAnimationFrame(() => requestAnimationFrame(() =>
+    {}
+  ));
+  if (document.readyState === "loading") {{
+    addEventListener("DOMContentLoaded", ping, {{ once: true }});
+  }} else {{
+    ping();
+  }}
+}}"#,
+        post_page_message(r#"{ $type: "firstPaint" }"#)
+    )
+}
+
+/// Reports the page entering and leaving fullscreen with the Fullscreen API. Fullscreen iframes
+/// are the fullscreen element of the top frame, so it's only watched there.
+fn fullscreen_change_script() -> String {
+    format!(
+        r#"if (window.top === window) {{
+  const report = () => {};
+  document.addEventListener("fullscreenchange", report);
+  document.addEventListener("webkitfullscreenchange", report);
+}}"#,
+        post_page_message(
+            r#"{ $type: "fullscreenChanged", fullscreen: !!(document.fullscreenElement ?? document.webkitFullscreenElement) }"#
+        )
+    )
+}
+
+/// Reports the page's content security policy violations.
+fn csp_violation_script() -> String {
+    format!(
+        r#"document.addEventListener("securitypolicyviolation", (e) => {{
+  const message = {{
+    $type: "cspViolation",
+    directive: e.effectiveDirective,
+    blocked_uri: e.blockedURI,
+    source_file: e.sourceFile || null,
+    line_number: e.lineNumber || null,
+    column_number: e.columnNumber || null,
+    sample: e.sample || null,
+    enforced: e.disposition === "enforce",
+  }};
+  {};
+}});"#,
+        post_page_message("message")
+    )
+}
+
+/// Lets the page send IPC messages that the client answers with `respondToPage`.
+const PAGE_RPC_SCRIPT: &str = r#"(() => {
+  if (window.top !== window) return;
+  const pending = new Map();
+  let nextId = 0;
+  Object.defineProperty(window, "__webviewRpc", {
+    value: (id, ok, value) => {
+      const request = pending.get(id);
+      if (!request) return;
+      pending.delete(id);
+      ok ? request.resolve(value) : request.reject(new Error(value));
+    },
+  });
+  window.webviewRequest = (message) => new Promise((resolve, reject) => {
+    const id = nextId++;
+    pending.set(id, { resolve, reject });
+    window.ipc.postMessage(JSON.stringify({ ...message, __rpc_id: id }));
+  });
+})();"#;
+
+/// Builds a script settling the promise of the page's `window.webviewRequest` call with `rpc_id`.
+fn respond_to_page_script(rpc_id: u64, data: &serde_json::Value, error: Option<&str>) -> String {
+    let (ok, value) = match error {
+        Some(message) => (false, serde_json::to_string(message).unwrap()),
+        None => (true, data.to_string()),
+    };
+    format!(
+        "window.__webviewRpc && window.__webviewRpc({}, {}, {});",
+        rpc_id, ok, value
+    )
+}
+
+/// Reads the `__rpc_id` of an IPC message sent with `window.webviewRequest`.
+fn ipc_rpc_id(body: &str) -> Option<u64> {
+    if !body.trim_start().starts_with('{') {
+        return None;
+    }
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()?
+        .get("__rpc_id")?
+        .as_u64()
+}
+
+/// Sets the height of the drag region at the top of the page, adding the listener that starts
+/// dragging the window the first time it's run.
+fn drag_region_script(height: f64) -> String {
+    format!(
+        r#"(() => {{
+  if (window.top !== window) return;
+  window.__webviewDragRegionHeight = {};
+  if (window.__webviewDragRegion) return;
+  window.__webviewDragRegion = true;
+  window.addEventListener("mousedown", (e) => {{
+    if (e.button !== 0 || e.defaultPrevented || e.clientY >= window.__webviewDragRegionHeight) return;
+    const exempt = "a, button, input, select, textarea, label, [contenteditable], [data-webview-no-drag]";
+    if (e.target instanceof Element && e.target.closest(exempt)) return;
+    {};
+  }});
+}})();"#,
+        height,
+        post_page_message(r#"{ $type: "startDrag" }"#)
+    )
+}
+
+/// Turns touch emulation on or off, adding the listeners that turn mouse input into touch events
+/// the first time it's run.
+fn touch_emulation_script(enabled: bool) -> String {
+    format!(
+        r#"(() => {{
+  window.__webviewTouchEmulation = {};
+  if (window.__webviewTouchEmulationInstalled) return;
+  window.__webviewTouchEmulationInstalled = true;
+  const maxTouchPoints = navigator.maxTouchPoints;
+  Object.defineProperty(navigator, "maxTouchPoints", {{
+    get: () => (window.__webviewTouchEmulation ? 1 : maxTouchPoints),
+    configurable: true,
+  }});
+  let target = null;
+  const dispatch = (type, e) => {{
+    const init = {{
+      identifier: 0,
+      target,
+      clientX: e.clientX,
+      clientY: e.clientY,
+      screenX: e.screenX,
+      screenY: e.screenY,
+      pageX: e.pageX,
+      pageY: e.pageY,
+    }};
+    const touches = type === "touchend" ? [] : [init];
+    let event;
+    try {{
+      const touch = new Touch(init);
+      event = new TouchEvent(type, {{
+        bubbles: true,
+        cancelable: true,
+        composed: true,
+        touches: touches.length ? [touch] : [],
+        targetTouches: touches.length ? [touch] : [],
+        changedTouches: [touch],
+        shiftKey: e.shiftKey,
+        ctrlKey: e.ctrlKey,
+        altKey: e.altKey,
+        metaKey: e.metaKey,
+      }});
+    }} catch {{
+      // Engines without touch support have no `Touch` constructor, so a plain event is dressed up.
+      event = new Event(type, {{ bubbles: true, cancelable: true, composed: true }});
+      Object.defineProperties(event, {{
+        touches: {{ value: touches }},
+        targetTouches: {{ value: touches }},
+        changedTouches: {{ value: [init] }},
+      }});
+    }}
+    if (!target.dispatchEvent(event)) {{
+      e.preventDefault();
+      e.stopImmediatePropagation();
+    }}
+  }};
+  const listen = (mouse, touch, start) => {{
+    window.addEventListener(mouse, (e) => {{
+      if (!window.__webviewTouchEmulation || e.button !== 0) return;
+      if (start) target = e.target;
+      if (!target) return;
+      dispatch(touch, e);
+      if (touch === "touchend") target = null;
+    }}, {{ capture: true }});
+  }};
+  listen("mousedown", "touchstart", true);
+  listen("mousemove", "touchmove", false);
+  listen("mouseup", "touchend", false);
+}})();"#,
+        enabled
+    )
+}
+
+/// Prevents pinch and ctrl + scroll zooming while leaving scrolling intact.
+const DISABLE_ZOOM_SCRIPT: &str = r#"(() => {
+  addEventListener("wheel", (e) => {
+    if (e.ctrlKey) e.preventDefault();
+  }, { passive: false, capture: true });
+  for (const type of ["gesturestart", "gesturechange", "gestureend"]) {
+    addEventListener(type, (e) => e.preventDefault(), { passive: false, capture: true });
+  }
+  const style = () => {
+    document.documentElement.style.touchAction = "pan-x pan-y";
+    const meta = document.createElement("meta");
+    meta.name = "viewport";
+    meta.content = "width=device-width, initial-scale=1, maximum-scale=1, user-scalable=no";
+    document.head?.append(meta);
+  };
+  if (document.readyState === "loading") {
+    addEventListener("DOMContentLoaded", style, { once: true });
+  } else {
+    style();
+  }
+})();"#;
+
+/// The `smoothScrolling`, `overscroll` and `scrollbarsVisible` options, which are applied with a
+/// stylesheet.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ScrollBehavior {
+    smooth_scrolling: Option<bool>,
+    overscroll: Option<bool>,
+    scrollbars_hidden: bool,
+}
+
+impl ScrollBehavior {
+    /// The stylesheet overriding the page's scroll styles.
+    fn css(&self) -> String {
+        let mut css = String::new();
+        match self.smooth_scrolling {
+            Some(true) => css.push_str("html { scroll-behavior: smooth !important; }"),
+            Some(false) => css.push_str("html, * { scroll-behavior: auto !important; }"),
+            None => (),
+        }
+        match self.overscroll {
+            Some(true) => css.push_str("html, body { overscroll-behavior: auto !important; }"),
+            Some(false) => css.push_str("html, body { overscroll-behavior: none !important; }"),
+            None => (),
+        }
+        if self.scrollbars_hidden {
+            css.push_str(
+                "::-webkit-scrollbar { display: none !important; } \
+                 html, * { scrollbar-width: none !important; }",
+            );
+        }
+        css
+    }
+
+    /// Builds a script that applies the stylesheet.
+    fn script(&self) -> String {
+        stylesheet_script("__webview-scroll-behavior", &self.css())
+    }
+}
+
+/// Builds a script that sets the contents of the stylesheet with the given id, adding it as soon
+/// as the document has a root element when it's run before the page.
+fn stylesheet_script(id: &str, css: &str) -> String {
+    format!(
+        r#"(() => {{
+  const apply = () => {{
+    let style = document.getElementById({id});
+    if (!style) {{
+      style = document.createElement("style");
+      style.id = {id};
+      (document.head ?? document.documentElement).append(style);
+    }}
+    style.textContent = {css};
+  }};
+  if (document.documentElement) {{
+    apply();
+  }} else {{
+    new MutationObserver((_, observer) => {{
+      if (document.documentElement) {{
+        observer.disconnect();
+        apply();
+      }}
+    }}).observe(document, {{ childList: true }});
+  }}
+}})();"#,
+        id = serde_json::to_string(id).unwrap(),
+        css = serde_json::to_string(css).unwrap()
+    )
+}
+
+/// Stops audible media playback that wasn't started shortly after a user gesture.
+const AUTOPLAY_MUTED_SCRIPT: &str = r#"(() => {
+  let gestureAt = -Infinity;
+  for (const type of ["pointerdown", "keydown", "touchend"]) {
+    window.addEventListener(type, () => { gestureAt = performance.now(); }, true);
+  }
+  const blocked = (media) => !media.muted && media.volume > 0 && performance.now() - gestureAt > 5000;
+  const play = HTMLMediaElement.prototype.play;
+  HTMLMediaElement.prototype.play = function () {
+    if (blocked(this)) {
+      return Promise.reject(new DOMException("Audible playback requires user interaction", "NotAllowedError"));
+    }
+    return play.call(this);
+  };
+  const check = (event) => {
+    if (event.target instanceof HTMLMediaElement && !event.target.paused && blocked(event.target)) {
+      event.target.pause();
+    }
+  };
+  document.addEventListener("play", check, true);
+  document.addEventListener("volumechange", check, true);
+})();"#;
+
+/// Clears the page background of content served from the `load-html` protocol.
+const TRANSPARENT_HTML_SCRIPT: &str = r#"if (location.protocol === "load-html:" || location.hostname.startsWith("load-html.")) {
+  const style = () => {
+    const element = document.createElement("style");
+    element.textContent = "html, body { background: transparent; }";
+    (document.head ?? document.documentElement).append(element);
+  };
+  if (document.readyState === "loading") {
+    addEventListener("DOMContentLoaded", style, { once: true });
+  } else {
+    style();
+  }
+}"#;
+
+/// The path of the `manifest` option on the `load-html` protocol.
+const MANIFEST_PATH: &str = "/__webview/manifest.webmanifest";
+
+/// Builds a script that links the `manifest` option from content served from the `load-html`
+/// protocol, and sets its theme color.
+fn manifest_script(theme_color: Option<&str>) -> String {
+    format!(
+        r#"if (location.protocol === "load-html:" || location.hostname.startsWith("load-html.")) {{
+  const insert = () => {{
+    const link = document.createElement("link");
+    link.rel = "manifest";
+    link.href = {};
+    (document.head ?? document.documentElement).append(link);
+    const themeColor = {};
+    if (themeColor !== null) {{
+      const meta = document.createElement("meta");
+      meta.name = "theme-color";
+      meta.content = themeColor;
+      (document.head ?? document.documentElement).append(meta);
+    }}
+  }};
+  if (document.readyState === "loading") {{
+    addEventListener("DOMContentLoaded", insert, {{ once: true }});
+  }} else {{
+    insert();
+  }}
+}}"#,
+        serde_json::to_string(MANIFEST_PATH).unwrap(),
+        serde_json::to_string(&theme_color).unwrap()
+    )
+}
+
+/// Looks up the encoding for a charset label, such as `utf-8` or `latin1`.
+fn html_encoding(charset: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(charset.trim().as_bytes())
+        .ok_or_else(|| format!("Unknown charset `{}`", charset))
+}
+
+/// Serves html encoded into `encoding`.
+fn serve_html(html: &str, encoding: &'static Encoding) -> HttpResponse<Cow<'static, [u8]>> {
+    let (body, encoding, _) = encoding.encode(html);
+    HttpResponse::builder()
+        .header(
+            "Content-Type",
+            format!("text/html; charset={}", encoding.name()),
+        )
+        .body(Cow::Owned(body.into_owned()))
+        .unwrap()
+}
+
+/// Serves the `manifest` option.
+fn serve_manifest(manifest: &[u8]) -> HttpResponse<Cow<'static, [u8]>> {
+    HttpResponse::builder()
+        .header("Content-Type", "application/manifest+json")
+        .body(Cow::Owned(manifest.to_vec()))
+        .unwrap()
+}
+
+/// Lets `requestAnimationFrame` callbacks be held back while the window is hidden. Callbacks
+/// requested while paused run once the page is resumed, so animation loops stop and pick up again.
+const PAUSE_ANIMATIONS_SCRIPT: &str = r#"(() => {
+  const request = window.requestAnimationFrame.bind(window);
+  const cancel = window.cancelAnimationFrame.bind(window);
+  const held = new Map();
+  let paused = false;
+  let nextId = -1;
+  window.requestAnimationFrame = (callback) => {
+    if (!paused) return request(callback);
+    held.set(nextId, callback);
+    return nextId--;
+  };
+  window.cancelAnimationFrame = (id) => id < 0 ? held.delete(id) : cancel(id);
+  Object.defineProperty(window, "__webviewSetPaused", {
+    value: (value) => {
+      if (value === paused) return;
+      paused = value;
+      window.dispatchEvent(new CustomEvent(paused ? "webviewpause" : "webviewresume"));
+      if (!paused) {
+        for (const callback of held.values()) request(callback);
+        held.clear();
+      }
+    },
+  });
+})();"#;
+
+/// Builds a script that pauses or resumes animations on the page.
+fn animations_paused_script(paused: bool) -> String {
+    format!("window.__webviewSetPaused?.({});", paused)
+}
+
+/// The largest value a binary `eval` request responds with, in bytes.
+const BINARY_EVAL_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Builds a script body that evaluates `js` with `eval`, given as the expression of the function to
+/// call, and resolves its binary value encoded as base64.
+fn binary_eval_script(eval: &str, js: &str) -> String {
+    let js = serde_json::to_string(js).unwrap();
+    format!(
+        r#"const value = await {eval}({js});
+// The value can come from a frame, whose classes differ from the top frame's.
+const tag = Object.prototype.toString.call(value);
+let bytes;
+if (tag === "[object ArrayBuffer]") {{
+  bytes = new Uint8Array(value);
+}} else if (ArrayBuffer.isView(value)) {{
+  bytes = new Uint8Array(value.buffer, value.byteOffset, value.byteLength);
+}} else if (tag === "[object Blob]" || tag === "[object File]") {{
+  bytes = new Uint8Array(await value.arrayBuffer());
+}} else {{
+  throw new Error("The result is not an ArrayBuffer, typed array, DataView or Blob but " + tag);
+}}
+if (bytes.byteLength > {BINARY_EVAL_MAX_BYTES}) {{
+  throw new Error("The result is " + bytes.byteLength + " bytes, more than the limit of {BINARY_EVAL_MAX_BYTES}");
+}}
+let binary = "";
+for (let i = 0; i < bytes.length; i += 0x8000) {{
+  binary += String.fromCharCode(...bytes.subarray(i, i + 0x8000));
+}}
+return btoa(binary);"#
+    )
+}
+
+/// The most matches counted by a find in page search.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const FIND_MAX_MATCHES: u32 = 1000;
+
+/// A step of a find in page search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FindStep {
+    Start { forward: bool },
+    Next,
+    Previous,
+}
+
+/// The find in page search in progress, tracked to report which match is active.
+#[derive(Debug)]
+struct FindState {
+    query: String,
+    match_case: bool,
+    /// The step waiting for its result.
+    step: FindStep,
+    active: u32,
+}
+
+impl Default for FindState {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            match_case: false,
+            step: FindStep::Start { forward: true },
+            active: 0,
+        }
+    }
+}
+
+impl FindState {
+    /// Starts the next step of a search, which continues the current one if the query is the same.
+    fn search(&mut self, query: &str, forward: bool, match_case: bool) -> FindStep {
+        self.step = match (
+            self.query == query && self.match_case == match_case,
+            forward,
+        ) {
+            (true, true) => FindStep::Next,
+            (true, false) => FindStep::Previous,
+            (false, forward) => FindStep::Start { forward },
+        };
+        self.query = query.to_string();
+        self.match_case = match_case;
+        self.step
+    }
+
+    /// Records the number of matches the current step found, returning the active match.
+    fn found(&mut self, matches: u32) -> u32 {
+        self.active = match self.step {
+            _ if matches == 0 => 0,
+            FindStep::Start { forward: true } => 1,
+            FindStep::Start { forward: false } => matches,
+            FindStep::Next => self.active % matches + 1,
+            FindStep::Previous if self.active <= 1 || self.active > matches => matches,
+            FindStep::Previous => self.active - 1,
+        };
+        self.active
+    }
+}
+
+/// Builds a script body that highlights the next match of `query` with `window.find`, resolving to
+/// the number of matches in the page.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn find_script(query: &str, step: FindStep, match_case: bool) -> String {
+    let backwards = matches!(
+        step,
+        FindStep::Previous | FindStep::Start { forward: false }
+    );
+    let start = if matches!(step, FindStep::Start { .. }) {
+        "window.getSelection()?.removeAllRanges();"
+    } else {
+        ""
+    };
+    format!(
+        r#"const query = {query};
+const normalize = (text) => {match_case} ? text : text.toLocaleLowerCase();
+const text = normalize(document.body?.innerText ?? "");
+const needle = normalize(query);
+let matches = 0;
+for (let index = text.indexOf(needle); index !== -1; index = text.indexOf(needle, index + needle.length)) matches++;
+{start}
+return matches > 0 && window.find(query, {match_case}, {backwards}, true) ? matches : 0;"#,
+        query = serde_json::to_string(query).unwrap(),
+    )
+}
+
+/// Reads the selected text, which for inputs and textareas isn't part of the document's selection.
+const GET_SELECTION_SCRIPT: &str = r#"const element = document.activeElement;
+if (element && typeof element.selectionStart === "number" && typeof element.value === "string") {
+  return element.value.slice(element.selectionStart, element.selectionEnd);
+}
+return window.getSelection()?.toString() ?? "";"#;
+
+/// Clears the document's selection and any selection in a focused input or textarea.
+const CLEAR_SELECTION_SCRIPT: &str = r#"(() => {
+  const element = document.activeElement;
+  if (element && typeof element.selectionStart === "number") {
+    element.setSelectionRange(element.selectionEnd, element.selectionEnd);
+  }
+  window.getSelection()?.removeAllRanges();
+})();"#;
+
+/// Builds a script body that clicks the element at `x`, `y` in the viewport, resolving to whether
+/// there was one.
+fn click_script(x: f64, y: f64) -> String {
+    format!(
+        r#"const target = document.elementFromPoint({x}, {y});
+if (!target) return false;
+const init = {{ bubbles: true, cancelable: true, composed: true, view: window, clientX: {x}, clientY: {y}, button: 0 }};
+target.dispatchEvent(new PointerEvent("pointerdown", {{ ...init, isPrimary: true, buttons: 1 }}));
+target.dispatchEvent(new MouseEvent("mousedown", {{ ...init, buttons: 1 }}));
+target.focus?.();
+target.dispatchEvent(new PointerEvent("pointerup", {{ ...init, isPrimary: true }}));
+target.dispatchEvent(new MouseEvent("mouseup", init));
+target.dispatchEvent(new MouseEvent("click", {{ ...init, detail: 1 }}));
+return true;"#
+    )
+}
+
+/// Lists the frames of the page with a selector for each that `frame_eval_script` can find it by.
+const GET_FRAMES_SCRIPT: &str = r##"const selector = (frame) => {
+  const parts = [];
+  for (let node = frame; node !== document.documentElement; node = node.parentElement) {
+    if (node.id && document.querySelectorAll("#" + CSS.escape(node.id)).length === 1) {
+      parts.unshift("#" + CSS.escape(node.id));
+      return parts.join(" > ");
+    }
+    parts.unshift(`${node.localName}:nth-child(${[...node.parentElement.children].indexOf(node) + 1})`);
+  }
+  return ["html", ...parts].join(" > ");
+};
+return [...document.querySelectorAll("iframe, frame")].map((frame) => {
+  let url = null;
+  let sameOrigin = false;
+  try {
+    url = frame.contentWindow.location.href;
+    sameOrigin = true;
+  } catch {
+    url = frame.src || null;
+  }
+  return { selector: selector(frame), name: frame.name || null, url, sameOrigin };
+});"##;
+
+/// Reads the page's navigation and paint timings.
+const PERFORMANCE_TIMING_SCRIPT: &str = r#"if (typeof performance === "undefined" || typeof performance.getEntriesByType !== "function") {
+  throw new Error("Performance timing isn't available");
+}
+const [navigation] = performance.getEntriesByType("navigation");
+const paint = performance.getEntriesByType("paint");
+const paintTime = (name) => paint.find((entry) => entry.name === name)?.startTime ?? null;
+const after = (time) => navigation && time > 0 ? time : null;
+return {
+  timeToFirstByte: navigation ? after(navigation.responseStart - navigation.startTime) : null,
+  domContentLoaded: after(navigation?.domContentLoadedEventEnd),
+  load: after(navigation?.loadEventEnd),
+  firstPaint: paintTime("first-paint"),
+  firstContentfulPaint: paintTime("first-contentful-paint"),
+  navigation: navigation?.toJSON() ?? null,
+  paint: paint.map((entry) => entry.toJSON()),
+};"#;
+
+/// Reads the page's web storage, leaving out the webview's own bookkeeping.
+const EXPORT_STORAGE_SCRIPT: &str = r#"const read = (storage) => Object.fromEntries(
+  Object.keys(storage)
+    .filter((key) => !key.startsWith("__webview"))
+    .map((key) => [key, storage.getItem(key)])
+);
+return { origin: location.origin, localStorage: read(localStorage), sessionStorage: read(sessionStorage) };"#;
+
+/// Builds the body of a function that writes `snapshot` into the page's storage if it's on the
+/// snapshot's origin and it hasn't been written in this tab yet, returning whether it was written.
+fn import_storage_script(id: i64, snapshot: &StorageSnapshot) -> String {
+    format!(
+        r#"const data = {data};
+if (location.origin !== data.origin || sessionStorage.getItem("__webviewStorageImport") === "{id}") return false;
+for (const [key, value] of Object.entries(data.localStorage)) localStorage.setItem(key, value);
+for (const [key, value] of Object.entries(data.sessionStorage)) sessionStorage.setItem(key, value);
+sessionStorage.setItem("__webviewStorageImport", "{id}");
+return true;"#,
+        data = serde_json::to_string(snapshot).unwrap()
+    )
+}
+
+/// The most urls kept for a single navigation, so a redirect loop can't grow it unbounded.
+const NAVIGATION_CHAIN_LIMIT: usize = 32;
+
+/// The urls visited by the most recent navigation, including its redirects.
+#[derive(Debug, Default)]
+struct NavigationChain {
+    urls: Vec<String>,
+    /// Whether the navigation has finished loading, so the next url starts a new chain.
+    finished: bool,
+}
+
+impl NavigationChain {
+    /// Records a url the navigation went to.
+    fn visit(&mut self, url: &str) {
+        if self.finished {
+            self.urls.clear();
+            self.finished = false;
+        }
+        // Navigations restarted with the default headers pass through twice.
+        if self.urls.last().map(String::as_str) == Some(url) {
+            return;
+        }
+        if self.urls.len() == NAVIGATION_CHAIN_LIMIT {
+            self.urls.remove(0);
+        }
+        self.urls.push(url.to_string());
+    }
+
+    /// Records the url the navigation finished loading at.
+    fn finish(&mut self, url: &str) {
+        self.visit(url);
+        self.finished = true;
+    }
+}
+
+/// Creates a new directory in the system's temporary directory for an ephemeral profile.
+fn create_profile_dir() -> wry::Result<PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let dir = env::temp_dir().join(format!("webview-profile-{}-{}", std::process::id(), nanos));
+    std::fs::create_dir_all(&dir).map_err(|err| {
+        startup_error(format!(
+            "Failed to create the ephemeral profile directory {}: {}",
+            dir.display(),
+            err
+        ))
+    })?;
+    debug!(dir = %dir.display(), "Created ephemeral profile");
+    Ok(dir)
+}
+
+/// Builds a script that navigates to `url` without adding a history entry.
+fn replace_location_script(url: &str) -> String {
+    format!("location.replace({});", serde_json::to_string(url).unwrap())
+}
+
+/// Builds a script that sets the page's `document.title`.
+fn document_title_script(title: &str) -> String {
+    format!(
+        "document.title = {};",
+        serde_json::to_string(title).unwrap()
+    )
+}
+
+/// Stops the find in page search, clearing its highlight.
+fn stop_find(webview: &wry::WebView, find_state: &RefCell<FindState>) {
+    *find_state.borrow_mut() = FindState::default();
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{FindControllerExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        if let Some(find_controller) = webview.webview().find_controller() {
+            find_controller.search_finish();
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if let Err(err) = webview.evaluate_script("window.getSelection()?.removeAllRanges();") {
+        error!("Failed to clear the find highlight: {:?}", err);
+    }
+}
+
+/// Warns when a transparent window will be drawn opaque because the screen has no RGBA visual or
+/// no compositor is running, and whenever the compositor stops or starts.
+#[cfg(target_os = "linux")]
+fn check_transparency(gtk_window: &gtk::ApplicationWindow) {
+    use gtk::prelude::{GtkWindowExt, WidgetExt};
+
+    let Some(screen) = GtkWindowExt::screen(gtk_window) else {
+        return;
+    };
+    if screen.rgba_visual().is_none() {
+        warn!("The screen has no RGBA visual, the `transparent` window will be opaque");
+    } else if gtk_window
+        .visual()
+        .is_some_and(|visual| Some(visual) != screen.rgba_visual())
+    {
+        warn!("The window was created without an RGBA visual, it will be opaque");
+    }
+    if !screen.is_composited() {
+        warn!("No compositor is running, the `transparent` window will be opaque until one starts");
+    }
+    screen.connect_composited_changed(|screen| {
+        if screen.is_composited() {
+            info!("A compositor started, the window can be transparent");
+        } else {
+            warn!("The compositor stopped, the `transparent` window will be opaque");
+        }
+    });
+}
+
+/// Clears the webview's memory cache and lets the page know it should free memory.
+fn release_memory(webview: &wry::WebView, level: MemoryPressureLevel) {
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{WebContextExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        if let Some(context) = webview.webview().context() {
+            context.clear_cache();
+        }
+    }
+    let script = format!(
+        "window.dispatchEvent(new CustomEvent(\"webviewmemorypressure\", {{ detail: {} }}));",
+        serde_json::to_string(&level).unwrap()
+    );
+    if let Err(err) = webview.evaluate_script(&script) {
+        error!("Failed to tell the page about memory pressure: {:?}", err);
+    }
+}
+
+/// Animates scrolling with the mouse wheel and keyboard where the platform has a setting for it.
+fn set_smooth_scrolling(webview: &wry::WebView, smooth_scrolling: bool) {
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{SettingsExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        if let Some(settings) = webview.webview().settings() {
+            settings.set_enable_smooth_scrolling(smooth_scrolling);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (webview, smooth_scrolling);
+}
+
+/// Scales the page's text without scaling the rest of the page.
+fn set_text_zoom(webview: &wry::WebView, factor: f64) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{SettingsExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        let view = webview.webview();
+        let settings = view
+            .settings()
+            .ok_or_else(|| "The webview has no settings".to_string())?;
+        settings.set_zoom_text_only(factor != 1.0);
+        view.set_zoom_level(factor);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (webview, factor);
+        Err("Text zoom is only supported on Linux".into())
+    }
+}
+
+/// Adds a script that's run in the top frame at the start of every page load.
+#[cfg(target_os = "linux")]
+fn add_user_script(webview: &wry::WebView, script: &str) -> webkit2gtk::UserScript {
+    use webkit2gtk::{
+        UserContentInjectedFrames, UserContentManagerExt, UserScriptInjectionTime, WebViewExt,
+    };
+    use wry::WebViewExtUnix;
+
+    let user_script = webkit2gtk::UserScript::new(
+        script,
+        UserContentInjectedFrames::TopFrame,
+        UserScriptInjectionTime::Start,
+        &[],
+        &[],
+    );
+    if let Some(manager) = webview.webview().user_content_manager() {
+        manager.add_script(&user_script);
+    }
+    user_script
+}
+
+/// Removes a script added with `add_user_script`.
+#[cfg(target_os = "linux")]
+fn remove_user_script(webview: &wry::WebView, user_script: &webkit2gtk::UserScript) {
+    use webkit2gtk::{UserContentManagerExt, WebViewExt};
+    use wry::WebViewExtUnix;
+
+    if let Some(manager) = webview.webview().user_content_manager() {
+        manager.remove_script(user_script);
+    }
+}
+
+/// Builds the response to a request once its correlated script reports back, or returns `None`
+/// if it'll send the response itself later.
+type EvalResponder = Box<dyn FnOnce(Result<serde_json::Value, String>) -> Option<Response>>;
+
+/// Runs the body of an async function in the page, calling `respond` with its outcome once it
+/// reports back. Returns an error response if the script couldn't be run at all.
+fn eval_correlated(
+    webview: &wry::WebView,
+    pending_evals: &mut HashMap<i64, EvalResponder>,
+    id: i64,
+    body: &str,
+    respond: impl FnOnce(Result<serde_json::Value, String>) -> Response + 'static,
+) -> Option<Response> {
+    eval_correlated_deferred(webview, pending_evals, id, body, move |result| {
+        Some(respond(result))
+    })
+}
+
+/// Like `eval_correlated`, but `respond` can return `None` to send its response later.
+fn eval_correlated_deferred(
+    webview: &wry::WebView,
+    pending_evals: &mut HashMap<i64, EvalResponder>,
+    id: i64,
+    body: &str,
+    respond: impl FnOnce(Result<serde_json::Value, String>) -> Option<Response> + 'static,
+) -> Option<Response> {
+    match webview.evaluate_script(&correlated_script(id, body)) {
+        Ok(_) => {
+            pending_evals.insert(id, Box::new(respond));
+            None
+        }
+        Err(err) => {
+            error!("Eval error: {:?}", err);
+            Some(Response::Err {
+                id,
+                message: err.to_string(),
+            })
+        }
+    }
+}
+
+/// Responds to a correlated script with its result as a `json` value.
+fn json_result(id: i64, result: Result<serde_json::Value, String>) -> Response {
+    match result {
+        Ok(value) => Response::Result {
+            id,
+            result: value.into(),
+        },
+        Err(message) => Response::Err { id, message },
+    }
+}
+
+/// The bounds of an element relative to the document, in CSS pixels.
+#[derive(Deserialize, Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct ElementRect {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+/// Builds a script body that resolves the document relative bounds of the element matching
+/// `selector`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn element_rect_script(selector: &str) -> String {
+    let selector = serde_json::to_string(selector).unwrap();
+    format!(
+        r#"const element = document.querySelector({selector});
+if (!element) {{
+  throw new Error("No element matches " + {selector});
+}}
+const rect = element.getBoundingClientRect();
+return {{ x: rect.x + scrollX, y: rect.y + scrollY, width: rect.width, height: rect.height }};"#
+    )
+}
+
+/// Crops a snapshot of the whole document to `rect` and encodes it as a PNG.
+#[cfg(target_os = "linux")]
+fn crop_to_png(snapshot: &cairo::Surface, rect: &ElementRect) -> Result<Vec<u8>, String> {
+    let (scale, _) = snapshot.device_scale();
+    let width = (rect.width * scale).ceil() as i32;
+    let height = (rect.height * scale).ceil() as i32;
+    if width <= 0 || height <= 0 {
+        return Err("The element has no size".into());
+    }
+
+    let cropped = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)
+        .map_err(|err| err.to_string())?;
+    cropped.set_device_scale(scale, scale);
+    let context = cairo::Context::new(&cropped).map_err(|err| err.to_string())?;
+    context
+        .set_source_surface(snapshot, -rect.x, -rect.y)
+        .map_err(|err| err.to_string())?;
+    context.paint().map_err(|err| err.to_string())?;
+    drop(context);
+
+    let mut png = Vec::new();
+    cropped
+        .write_to_png(&mut png)
+        .map_err(|err| err.to_string())?;
+    Ok(png)
+}
+
+/// Builds a script body that evaluates `js` once the document has finished loading.
+fn when_ready_script(js: &str) -> String {
+    format!(
+        r#"if (document.readyState !== "complete") {{
+  await new Promise((resolve) => addEventListener("load", resolve, {{ once: true }}));
+}}
+return (0, eval)({});"#,
+        serde_json::to_string(js).unwrap()
+    )
+}
+
+/// Builds a script body that evaluates `js` inside the same-origin iframe matching `selector`.
+fn frame_eval_script(selector: &str, js: &str, binary: bool) -> String {
+    let selector = serde_json::to_string(selector).unwrap();
+    let eval = match binary {
+        true => binary_eval_script("target.eval", js),
+        false => format!("target.eval({});", serde_json::to_string(js).unwrap()),
+    };
+    format!(
+        r#"const frame = document.querySelector({selector});
+if (!(frame instanceof HTMLIFrameElement || frame instanceof HTMLFrameElement)) {{
+  throw new Error("No frame matches " + {selector});
+}}
+let target;
+try {{
+  target = frame.contentWindow;
+  target.document;
+}} catch {{
+  throw new Error("Cannot evaluate in cross-origin frame " + {selector});
+}}
+{eval}"#
+    )
+}
+
+/// Watches a directory recursively, notifying the event loop whenever something in it changes.
+#[cfg(feature = "watch")]
+fn watch_directory(
+    path: &str,
+    proxy: tao::event_loop::EventLoopProxy<UserEvent>,
+) -> notify::Result<notify::RecommendedWatcher> {
+    use notify::{RecursiveMode, Watcher};
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if !event.kind.is_access() => {
+                debug!(paths = ?event.paths, "Watched files changed");
+                let _ = proxy.send_event(UserEvent::FileChanged);
+            }
+            Ok(_) => (),
+            Err(err) => error!("Watch error: {:?}", err),
+        })?;
+    watcher.watch(Path::new(path), RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+/// Coalesces a rapidly changing value so it's emitted at most once per interval.
+///
+/// The first value after a quiet period is emitted immediately. Values arriving within the
+/// interval are latched and only the latest one is emitted once the interval elapses.
+struct Throttle<T> {
+    interval: Duration,
+    last_emitted: Option<Instant>,
+    pending: Option<T>,
+}
+
+impl<T> Throttle<T> {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_emitted: None,
+            pending: None,
+        }
+    }
+
+    /// Records a new value, returning it if it should be emitted right away.
+    fn push(&mut self, value: T, now: Instant) -> Option<T> {
+        match self.last_emitted {
+            Some(last) if now.duration_since(last) < self.interval => {
+                self.pending = Some(value);
+                None
+            }
+            _ => {
+                self.last_emitted = Some(now);
+                self.pending = None;
+                Some(value)
+            }
+        }
+    }
+
+    /// Returns the latched value if the interval since the last emission has elapsed.
+    fn flush(&mut self, now: Instant) -> Option<T> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.last_emitted = Some(now);
+                self.pending.take()
+            }
+            _ => None,
+        }
+    }
+
+    /// The instant at which the latched value is due, if there is one.
+    fn deadline(&self) -> Option<Instant> {
+        match (&self.pending, self.last_emitted) {
+            (Some(_), Some(last)) => Some(last + self.interval),
+            _ => None,
+        }
+    }
+}
+
+/// Adds the `language` option to the `defaultHeaders` option as an `Accept-Language` header,
+/// unless they already have one.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn with_language(
+    mut default_headers: HashMap<String, String>,
+    language: Option<&str>,
+) -> HashMap<String, String> {
+    if let Some(language) = language {
+        if !default_headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("accept-language"))
+        {
+            default_headers.insert("Accept-Language".to_string(), language.to_string());
+        }
+    }
+    default_headers
+}
+
+/// Sets the `Referer` header of `headers`, replacing any it already has.
+fn with_referer(
+    mut headers: HashMap<String, String>,
+    referer: Option<&str>,
+) -> HashMap<String, String> {
+    if let Some(referer) = referer {
+        headers.retain(|name, _| !name.eq_ignore_ascii_case("referer"));
+        headers.insert("Referer".to_string(), referer.to_string());
+    }
+    headers
+}
+
+/// Adds a `<meta name="referrer">` with the given policy to the page, as soon as it has a root element.
+fn referrer_policy_script(policy: ReferrerPolicy) -> String {
+    format!(
+        r#"(() => {{
+  const meta = document.createElement("meta");
+  meta.name = "referrer";
+  meta.content = {};
+  const insert = () => {{
+    const parent = document.head || document.documentElement;
+    if (parent) parent.prepend(meta);
+    return !!parent;
+  }};
+  if (!insert()) {{
+    const observer = new MutationObserver(() => {{
+      if (insert()) observer.disconnect();
+    }});
+    observer.observe(document, {{ childList: true, subtree: true }});
+  }}
+}})();"#,
+        serde_json::to_string(policy.as_str()).unwrap()
+    )
+}
+
+/// Adds the `defaultHeaders` option to the headers of a navigation, with the navigation's own
+/// headers taking precedence.
+fn with_default_headers(
+    default_headers: &HashMap<String, String>,
+    headers: Option<HashMap<String, String>>,
+) -> Option<HashMap<String, String>> {
+    if default_headers.is_empty() {
+        return headers;
+    }
+    let mut merged = default_headers.clone();
+    merged.extend(headers.unwrap_or_default());
+    Some(merged)
+}
+
+/// Converts headers provided by the client into a header map, skipping any that are invalid.
+fn parse_headers(headers: HashMap<String, String>) -> HeaderMap {
+    headers
+        .into_iter()
+        .filter_map(|(name, value)| {
+            match (HeaderName::from_str(&name), HeaderValue::from_str(&value)) {
+                (Ok(name), Ok(value)) => Some((name, value)),
+                _ => {
+                    error!(name, value, "Ignoring invalid header");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Starts a thread that calls `on_timeout` with each value sent to it once its deadline has passed,
+/// so timeouts don't need a thread each. Values are expected in the order of their deadlines.
+fn spawn_timeouts<T: Send + 'static>(
+    on_timeout: impl Fn(T) + Send + 'static,
+) -> Sender<(Instant, T)> {
+    let (sender, receiver) = mpsc::channel::<(Instant, T)>();
+    std::thread::spawn(move || {
+        let mut waiting = std::collections::VecDeque::<(Instant, T)>::new();
+        loop {
+            let received = match waiting.front() {
+                Some((at, _)) => {
+                    receiver.recv_timeout(at.saturating_duration_since(Instant::now()))
+                }
+                None => receiver
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(timeout) => waiting.push_back(timeout),
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            let now = Instant::now();
+            while waiting.front().is_some_and(|(at, _)| now >= *at) {
+                let (_, value) = waiting.pop_front().unwrap();
+                on_timeout(value);
+            }
+        }
+    });
+    sender
+}
+
+/// Converts a request to the client protocol scheme into the notification sent to the client.
+fn protocol_request_notification(
+    request_id: u64,
+    request: &wry::http::Request<Vec<u8>>,
+) -> Notification {
+    let mut headers = HashMap::<String, String>::new();
+    for (name, value) in request.headers() {
+        let Ok(value) = value.to_str() else {
+            warn!(%name, "Dropping non-UTF-8 request header");
+            continue;
+        };
+        headers
+            .entry(name.to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    Notification::ProtocolRequest {
+        request_id,
+        url: request.uri().to_string(),
+        method: request.method().to_string(),
+        headers,
+        body: BASE64.encode(request.body()),
+    }
+}
+
+/// Builds the response for a client protocol request from the client's `protocolResponse`.
+fn protocol_response(
+    status: u16,
+    headers: HashMap<String, String>,
+    body: &str,
+) -> Result<HttpResponse<Cow<'static, [u8]>>, String> {
+    let body = BASE64
+        .decode(body)
+        .map_err(|err| format!("Invalid base64 body: {}", err))?;
+    let mut response = HttpResponse::builder()
+        .status(status)
+        .body(Cow::Owned(body))
+        .map_err(|err| err.to_string())?;
+    response.headers_mut().extend(parse_headers(headers));
+    Ok(response)
+}
+
+/// Guesses the content type of a file served from a directory based on its extension.
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html" | "htm") => "text/html",
+        Some("js" | "mjs") => "text/javascript",
+        Some("css") => "text/css",
+        Some("json" | "map") => "application/json",
+        Some("wasm") => "application/wasm",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The scheme the `fonts` option's files are served from.
+const FONT_SCHEME: &str = "webview-font";
+
+/// Reads the file of a font from the `fonts` option.
+fn load_font(font: &Font) -> Result<Vec<u8>, String> {
+    match (&font.path, &font.data) {
+        (Some(path), _) => std::fs::read(path).map_err(|err| format!("{}: {}", path, err)),
+        (None, Some(data)) => BASE64.decode(data).map_err(|err| err.to_string()),
+        (None, None) => Err("It has neither a `path` nor `data`".into()),
+    }
+}
+
+/// Determines the MIME type of a font file from its first bytes.
+fn font_mime_type(font: &[u8]) -> &'static str {
+    match font.get(..4) {
+        Some(b"wOF2") => "font/woff2",
+        Some(b"wOFF") => "font/woff",
+        Some(b"OTTO") => "font/otf",
+        Some(b"\0\x01\0\0" | b"true") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Builds the `@font-face` rule for a font from the `fonts` option served from `url`.
+fn font_face_css(font: &Font, url: &str) -> String {
+    // Descriptors are inserted as they are, so they're limited to what's valid in them.
+    let descriptor = |name: &str, value: &Option<String>| match value {
+        Some(value)
+            if value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || " .%-".contains(c)) =>
+        {
+            format!(" {}: {};", name, value)
+        }
+        Some(value) => {
+            error!(value, "Ignoring invalid font {}", name);
+            String::new()
+        }
+        None => String::new(),
+    };
+    format!(
+        "@font-face {{ font-family: {}; src: url({});{}{} }}",
+        css_string(&font.family),
+        css_string(url),
+        descriptor("font-weight", &font.weight),
+        descriptor("font-style", &font.style),
+    )
+}
+
+/// Quotes a value as a CSS string.
+fn css_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' | '\r' | '\u{c}' => quoted.push_str(&format!("\\{:x} ", c as u32)),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Resolves a request path against the directory being served.
+///
+/// Returns `None` if the file doesn't exist or resolves to somewhere outside of the directory.
+fn resolve_directory_path(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(request_path)
+        .decode_utf8()
+        .ok()?;
+    let root = root.canonicalize().ok()?;
+    let mut path = root
+        .join(decoded.trim_start_matches('/'))
+        .canonicalize()
+        .ok()?;
+    if path.is_dir() {
+        path = path.join(default_entry()).canonicalize().ok()?;
+    }
+    path.starts_with(&root).then_some(path)
+}
+
+/// Adds the `protocolHeaders` to a response served from a custom protocol, replacing any the
+/// webview set itself.
+fn with_protocol_headers(
+    mut response: HttpResponse<Cow<'static, [u8]>>,
+    headers: &HeaderMap,
+) -> HttpResponse<Cow<'static, [u8]>> {
+    for (name, value) in headers {
+        response.headers_mut().insert(name, value.clone());
+    }
+    response
+}
+
+/// Adds the `contentSecurityPolicy` to a response served from a custom protocol, filling in a new
+/// nonce and adding it to the tags of html.
+fn with_content_security_policy(
+    mut response: HttpResponse<Cow<'static, [u8]>>,
+    policy: Option<&str>,
+) -> HttpResponse<Cow<'static, [u8]>> {
+    let Some(policy) = policy else {
+        return response;
+    };
+    let nonce = policy.contains("{nonce}").then(new_nonce);
+    let header = match &nonce {
+        Some(nonce) => policy.replace("{nonce}", nonce),
+        None => policy.to_string(),
+    };
+    match HeaderValue::from_str(&header) {
+        Ok(value) => {
+            response
+                .headers_mut()
+                .insert("Content-Security-Policy", value);
+        }
+        Err(_) => {
+            error!("Ignoring invalid `contentSecurityPolicy` option");
+            return response;
+        }
+    }
+    let is_html = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if let Some(nonce) = nonce.filter(|_| is_html) {
+        if let Ok(html) = std::str::from_utf8(response.body()) {
+            *response.body_mut() = Cow::Owned(add_nonce(html, &nonce).into_bytes());
+        }
+    }
+    response
+}
+
+/// Generates a random nonce for a content security policy.
+fn new_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate a random nonce");
+    BASE64.encode(bytes)
+}
+
+/// Adds a `nonce` attribute to the `<script>` and `<style>` tags of html. It's added before any
+/// other attributes, so it takes precedence over a nonce the tag already has.
+fn add_nonce(html: &str, nonce: &str) -> String {
+    // Lowercasing ASCII keeps byte offsets the same.
+    let lowercase = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut at = 0;
+    while let Some(start) = lowercase[at..].find('<').map(|start| at + start) {
+        at = start + 1;
+        let Some(tag) = ["script", "style"].into_iter().find(|tag| {
+            lowercase[at..].starts_with(tag)
+                && lowercase[at + tag.len()..]
+                    .starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        }) else {
+            continue;
+        };
+        at += tag.len();
+        out.push_str(&html[copied..at]);
+        out.push_str(&format!(" nonce=\"{}\"", nonce));
+        copied = at;
+        // The contents are raw text, which can't hold tags.
+        match lowercase[at..].find(&format!("</{}", tag)) {
+            Some(end) => at += end + 2,
+            None => break,
+        }
+    }
+    out.push_str(&html[copied..]);
+    out
+}
+
+/// Serves a file from a directory in response to a custom protocol request.
+fn serve_directory(root: &Path, request_path: &str) -> HttpResponse<Cow<'static, [u8]>> {
+    let file = resolve_directory_path(root, request_path)
+        .and_then(|path| std::fs::read(&path).ok().map(|body| (path, body)));
+    match file {
+        Some((path, body)) => HttpResponse::builder()
+            .header("Content-Type", mime_type(&path))
+            .body(Cow::Owned(body))
+            .unwrap(),
+        None => {
+            debug!(path = request_path, "File not found in served directory");
+            HttpResponse::builder()
+                .status(404)
+                .body(Cow::Borrowed(&[][..]))
+                .unwrap()
+        }
+    }
+}
+
+/// The bounded queue of requests waiting to be processed by the event loop.
+struct RequestQueue {
+    sender: SyncSender<Request>,
+    overflow: OverflowPolicy,
+    /// Used to respond to requests that are rejected because the queue is full.
+    responses: Sender<Message>,
+    /// Whether to send each request's JSON back in a `requestReceived` notification.
+    echo_requests: bool,
+}
+
+impl RequestQueue {
+    /// Queues a request for the event loop, applying the overflow policy if the queue is full.
+    fn push(&self, request: Request) {
+        match self.overflow {
+            OverflowPolicy::Block => self.sender.send(request).unwrap(),
+            OverflowPolicy::Reject => match self.sender.try_send(request) {
+                Ok(()) => (),
+                Err(TrySendError::Full(request)) => {
+                    warn!(request = ?request, "Request queue is full, rejecting request");
+                    self.responses
+                        .send(Message::Response(Response::Err {
+                            id: request.id(),
+                            message: "Request queue is full".to_string(),
+                        }))
+                        .unwrap();
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    panic!("Event loop is no longer receiving requests")
+                }
+            },
+        }
+    }
+}
+
+/// Incrementally parses JSON input from a reader and pushes the parsed requests onto a queue.
+///
+/// This is used in the main program to read JSON input from stdin and send it to the webview
+/// event loop.
+fn process_input<R: Read + std::marker::Send + 'static>(reader: BufReader<R>, queue: RequestQueue) {
+    std::thread::spawn(move || {
+        let feeder = BufReaderJsonFeeder::new(reader);
+        let mut parser = JsonParser::new_with_options(
+            feeder,
+            JsonParserOptionsBuilder::default()
+                .with_streaming(true)
+                .build(),
+        );
+
+        let mut json_string = String::new();
+        let mut depth = 0;
+
+        while let Some(event) = parser.next_event().unwrap() {
+            // Elements of an array need separating, field names add their own separator.
+            let is_value = !matches!(
+                event,
+                JsonEvent::NeedMoreInput
+                    | JsonEvent::EndObject
+                    | JsonEvent::EndArray
+                    | JsonEvent::FieldName
+            );
+            if is_value && !json_string.is_empty() && !json_string.ends_with(['[', '{', ':']) {
+                json_string.push(',');
+            }
+            match event {
+                JsonEvent::NeedMoreInput => parser.feeder.fill_buf().unwrap(),
+                JsonEvent::StartObject => {
+                    depth += 1;
+                    json_string.push('{');
+                }
+                JsonEvent::EndObject => {
+                    depth -= 1;
+                    json_string.push('}');
+
+                    // If we're back at depth 0, we have a complete JSON object
+                    if depth == 0 {
+                        if queue.echo_requests {
+                            let _ = queue.responses.send(Message::Notification(
+                                Notification::RequestReceived {
+                                    raw: json_string.clone(),
+                                },
+                            ));
+                        }
+                        match serde_json::from_str::<Request>(&json_string) {
+                            Ok(request) => {
+                                debug!(request = ?request, "Received request from client");
+                                queue.push(request)
+                            }
+                            Err(e) => error!("Failed to deserialize request: {:?}", e),
+                        }
+                        json_string.clear();
+                    }
+                }
+                JsonEvent::StartArray => {
+                    depth += 1;
+                    json_string.push('[');
+                }
+                JsonEvent::EndArray => {
+                    depth -= 1;
+                    json_string.push(']');
+                }
+                JsonEvent::FieldName => {
+                    if json_string.ends_with('{') {
+                        json_string.push('"');
+                    } else {
+                        json_string.push_str(",\"");
+                    }
+                    json_string.push_str(parser.current_str().unwrap());
                     json_string.push_str("\":");
                 }
-                JsonEvent::ValueString => {
-                    json_string.push('"');
-                    json_string.push_str(parser.current_str().unwrap());
-                    json_string.push('"');
+                JsonEvent::ValueString => {
+                    json_string.push('"');
+                    json_string.push_str(parser.current_str().unwrap());
+                    json_string.push('"');
+                }
+                // Numbers are re-emitted verbatim so values outside the range of `i64` or `f64`
+                // aren't lost or mangled before serde sees them.
+                JsonEvent::ValueInt | JsonEvent::ValueFloat => {
+                    json_string.push_str(parser.current_str().unwrap());
+                }
+                JsonEvent::ValueTrue => json_string.push_str("true"),
+                JsonEvent::ValueFalse => json_string.push_str("false"),
+                JsonEvent::ValueNull => json_string.push_str("null"),
+            }
+        }
+    });
+}
+
+/// How long exiting waits for the `closed` notification to be written.
+const CLOSED_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Forwards `SIGTERM`, `SIGINT` and `SIGHUP` to the event loop so the webview can close gracefully.
+///
+/// Signal handlers can hardly do anything safely, so the handler writes the signal to a pipe that a
+/// thread reads from. Once one's been received the default handlers are restored, so a second
+/// signal stops the process right away.
+#[cfg(unix)]
+fn forward_signals(proxy: tao::event_loop::EventLoopProxy<UserEvent>) -> std::io::Result<()> {
+    use std::os::fd::FromRawFd;
+    use std::sync::atomic::AtomicI32;
+
+    const SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+    static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn handle_signal(signal: libc::c_int) {
+        let byte = signal as u8;
+        // SAFETY: `write` is async-signal-safe and the pipe is never closed.
+        unsafe {
+            libc::write(
+                SIGNAL_PIPE.load(Ordering::Relaxed),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors `pipe` writes.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+    // SAFETY: The read end was just opened and nothing else owns it.
+    let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+    for signal in SIGNALS {
+        // SAFETY: The handler only calls async-signal-safe functions.
+        unsafe {
+            libc::signal(
+                signal,
+                handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+
+    std::thread::Builder::new()
+        .name("webview-signals".into())
+        .spawn(move || {
+            let mut byte = [0u8];
+            if reader.read_exact(&mut byte).is_ok() {
+                for signal in SIGNALS {
+                    // SAFETY: Restoring the default disposition is always sound.
+                    unsafe { libc::signal(signal, libc::SIG_DFL) };
+                }
+                let _ = proxy.send_event(UserEvent::Signal(byte[0] as i32));
+            }
+        })?;
+    Ok(())
+}
+
+/// The name of the thread writing messages to the client.
+const OUTPUT_THREAD_NAME: &str = "webview-output";
+
+/// Incrementally writes messages to a writer.
+///
+/// This is used in the main program to write messages to stdout. The returned receiver is sent to
+/// once the `closed` notification has been written, so exiting can wait for it.
+fn process_output<W: Write + std::marker::Send + 'static>(
+    writer: W,
+    receiver: mpsc::Receiver<Message>,
+    deadline: Option<OutputDeadline>,
+) -> mpsc::Receiver<()> {
+    let (closed_tx, closed_rx) = mpsc::sync_channel(1);
+    // When the current write started, so a client that stopped reading can be noticed.
+    let writing_since = Arc::new(Mutex::new(None::<Instant>));
+    if let Some(deadline) = deadline {
+        watch_output(Arc::downgrade(&writing_since), deadline);
+    }
+
+    let builder = std::thread::Builder::new().name(OUTPUT_THREAD_NAME.into());
+    builder
+        .spawn(move || {
+            let mut writer = std::io::BufWriter::new(writer);
+
+            while let Ok(event) = receiver.recv() {
+                debug!(message = ?event, "Sending message to client");
+                match serde_json::to_string(&event) {
+                    Ok(json) => {
+                        let mut buffer = json.into_bytes();
+                        buffer.push(b'\n');
+                        *writing_since.lock() = Some(Instant::now());
+                        writer.write_all(&buffer).unwrap();
+                        writer.flush().unwrap();
+                        *writing_since.lock() = None;
+                        if matches!(event, Message::Notification(Notification::Closed { .. })) {
+                            let _ = closed_tx.try_send(());
+                        }
+                    }
+                    Err(err) => {
+                        error!("Failed to serialize event: {:?} {:?}", event, err);
+                    }
+                }
+            }
+        })
+        .unwrap();
+    closed_rx
+}
+
+/// How long a message may take to be written before the client is considered to have stopped
+/// reading its output.
+#[derive(Debug, Clone, Copy)]
+struct OutputDeadline {
+    after: Duration,
+    exit: bool,
+}
+
+/// Warns when a write to the client has been blocked for longer than the deadline, which happens
+/// when the client isn't reading and the pipe's buffer fills up. Stops once the output thread does.
+fn watch_output(writing_since: std::sync::Weak<Mutex<Option<Instant>>>, deadline: OutputDeadline) {
+    let interval = (deadline.after / 4).max(Duration::from_millis(10));
+    std::thread::spawn(move || {
+        let mut warned = false;
+        while let Some(writing_since) = writing_since.upgrade() {
+            let stalled = writing_since
+                .lock()
+                .is_some_and(|since| since.elapsed() >= deadline.after);
+            drop(writing_since);
+            if stalled && !warned {
+                warned = true;
+                warn!(
+                    "A message has been waiting to be read by the client for over {:?}, is it reading the webview's output?",
+                    deadline.after
+                );
+                if deadline.exit {
+                    error!("Exiting since the client stopped reading");
+                    std::process::exit(1);
+                }
+            } else if !stalled {
+                warned = false;
+            }
+            std::thread::sleep(interval);
+        }
+    });
+}
+
+/// Where `RpcLogLayer` sends log records, set when `logsOverRpc` is enabled.
+static RPC_LOG_SINK: Mutex<Option<Sender<Message>>> = parking_lot::const_mutex(None);
+
+/// The maximum number of log records sent to the client per second.
+const RPC_LOG_RATE: u32 = 100;
+
+/// Limits how many records are let through per one second window, counting the ones dropped.
+#[derive(Default)]
+struct RateLimit {
+    window_start: Option<Instant>,
+    count: u32,
+    dropped: u64,
+}
+
+impl RateLimit {
+    /// Returns whether a record may be sent, and how many were dropped in the previous window if
+    /// this starts a new one.
+    fn allow(&mut self, now: Instant, limit: u32) -> (bool, Option<u64>) {
+        let mut dropped = None;
+        let new_window = match self.window_start {
+            Some(start) => now.duration_since(start) >= Duration::from_secs(1),
+            None => true,
+        };
+        if new_window {
+            self.window_start = Some(now);
+            self.count = 0;
+            dropped = Some(std::mem::take(&mut self.dropped)).filter(|dropped| *dropped > 0);
+        }
+        if self.count < limit {
+            self.count += 1;
+            (true, dropped)
+        } else {
+            self.dropped += 1;
+            (false, dropped)
+        }
+    }
+}
+
+/// Collects the fields of a log record into a single line.
+#[derive(Default)]
+struct LogVisitor {
+    message: String,
+    fields: Vec<String>,
+}
+
+impl tracing::field::Visit for LogVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+/// A tracing layer that forwards log records to the client as `log` notifications once
+/// `logsOverRpc` is enabled.
+///
+/// Records logged while writing to the client are skipped, since sending them would log again.
+#[derive(Default)]
+pub struct RpcLogLayer {
+    rate_limit: Mutex<RateLimit>,
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RpcLogLayer {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        if std::thread::current().name() == Some(OUTPUT_THREAD_NAME) {
+            return;
+        }
+        let sink = RPC_LOG_SINK.lock();
+        let Some(sender) = sink.as_ref() else {
+            return;
+        };
+
+        let (allowed, dropped) = self.rate_limit.lock().allow(Instant::now(), RPC_LOG_RATE);
+        if let Some(dropped) = dropped {
+            let _ = sender.send(Message::Notification(Notification::Log {
+                level: LogLevel::Warn,
+                target: module_path!().into(),
+                message: format!("Dropped {} log records", dropped),
+            }));
+        }
+        if !allowed {
+            return;
+        }
+
+        let mut visitor = LogVisitor::default();
+        event.record(&mut visitor);
+        let mut message = visitor.message;
+        for field in visitor.fields {
+            if !message.is_empty() {
+                message.push(' ');
+            }
+            message.push_str(&field);
+        }
+        let _ = sender.send(Message::Notification(Notification::Log {
+            level: LogLevel::from_tracing(event.metadata().level()),
+            target: event.metadata().target().into(),
+            message,
+        }));
+    }
+}
+
+/// A problem found while checking options without starting the webview.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionsError {
+    /// The top level option the problem relates to, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    /// A description of the problem.
+    message: String,
+}
+
+/// The outcome of one of the environment checks run by `doctor`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticCheck {
+    /// What was checked.
+    pub name: &'static str,
+    /// Whether the check passed. Informational checks always pass.
+    pub ok: bool,
+    /// What was found, or how to fix it when the check failed.
+    pub detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(name: &'static str, result: Result<String, String>) -> Self {
+        let ok = result.is_ok();
+        let detail = result.unwrap_or_else(|err| err);
+        Self { name, ok, detail }
+    }
+}
+
+/// Checks that the environment can run the webview by creating a hidden window and webview the
+/// same way `run` does, stopping at the first step that fails.
+///
+/// It creates the process's event loop, so it can't be followed by `run`.
+pub fn doctor() -> Vec<DiagnosticCheck> {
+    let mut checks = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    {
+        let display = match missing_display() {
+            Some(message) => Err(message),
+            None => Ok(["DISPLAY", "WAYLAND_DISPLAY"]
+                .iter()
+                .filter_map(|name| {
+                    env::var(name)
+                        .ok()
+                        .map(|value| format!("{}={}", name, value))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")),
+        };
+        let failed = display.is_err();
+        checks.push(DiagnosticCheck::new("display", display));
+        if failed {
+            return checks;
+        }
+    }
+
+    let event_loop =
+        match std::panic::catch_unwind(|| EventLoopBuilder::<UserEvent>::with_user_event().build())
+        {
+            Ok(event_loop) => event_loop,
+            Err(_) => {
+                checks.push(DiagnosticCheck::new(
+                    "eventLoop",
+                    Err(
+                        "Failed to initialize the windowing system, check that a display server \
+                         or compositor is running and accessible."
+                            .into(),
+                    ),
+                ));
+                return checks;
+            }
+        };
+    checks.push(DiagnosticCheck::new(
+        "eventLoop",
+        Ok("Initialized the windowing system".into()),
+    ));
+
+    #[cfg(target_os = "linux")]
+    {
+        use gtk::glib::prelude::ObjectExt;
+
+        let backend = gtk::gdk::Display::default()
+            .map(|display| display.type_().name().to_string())
+            .ok_or_else(|| "GDK has no default display".to_string());
+        checks.push(DiagnosticCheck::new("gdkBackend", backend));
+    }
+
+    let window = match WindowBuilder::new().with_visible(false).build(&event_loop) {
+        Ok(window) => window,
+        Err(err) => {
+            checks.push(DiagnosticCheck::new(
+                "window",
+                Err(format!("Failed to create a window: {}", err)),
+            ));
+            return checks;
+        }
+    };
+    checks.push(DiagnosticCheck::new(
+        "window",
+        Ok("Created a window".into()),
+    ));
+
+    let webview_builder = WebViewBuilder::new().with_html("<!doctype html>");
+    #[cfg(not(target_os = "linux"))]
+    let webview = webview_builder.build(&window);
+    #[cfg(target_os = "linux")]
+    let webview = {
+        use tao::platform::unix::WindowExtUnix;
+        use wry::WebViewBuilderExtUnix;
+        webview_builder.build_gtk(window.default_vbox().unwrap())
+    };
+    let webview = match webview {
+        Ok(webview) => webview,
+        Err(err) => {
+            checks.push(DiagnosticCheck::new(
+                "webview",
+                Err(format!("Failed to create a webview: {}", err)),
+            ));
+            return checks;
+        }
+    };
+    checks.push(DiagnosticCheck::new(
+        "webview",
+        wry::webview_version()
+            .map(|version| format!("Created a webview, backend version {}", version))
+            .map_err(|err| format!("Created a webview but its version is unknown: {}", err)),
+    ));
+
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{SettingsExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        let policy = webview
+            .webview()
+            .settings()
+            .map(|settings| format!("{:?}", settings.hardware_acceleration_policy()))
+            .unwrap_or_else(|| "unknown".into());
+        checks.push(DiagnosticCheck::new(
+            "hardwareAcceleration",
+            Ok(format!("Policy is {}", policy)),
+        ));
+
+        let render_nodes = std::fs::read_dir("/dev/dri")
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("renderD"))
+                    .count()
+            })
+            .unwrap_or(0);
+        checks.push(DiagnosticCheck::new(
+            "gpu",
+            if render_nodes > 0 {
+                Ok(format!("Found {} render node(s) in /dev/dri", render_nodes))
+            } else {
+                Err(
+                    "No render nodes in /dev/dri, rendering falls back to software. If pages \
+                     are blank, try setting WEBKIT_DISABLE_DMABUF_RENDERER=1 or \
+                     WEBKIT_DISABLE_COMPOSITING_MODE=1."
+                        .into(),
+                )
+            },
+        ));
+
+        let overrides = [
+            "WEBKIT_DISABLE_DMABUF_RENDERER",
+            "WEBKIT_DISABLE_COMPOSITING_MODE",
+            "LIBGL_ALWAYS_SOFTWARE",
+            "GDK_BACKEND",
+        ]
+        .iter()
+        .filter_map(|name| {
+            env::var(name)
+                .ok()
+                .map(|value| format!("{}={}", name, value))
+        })
+        .collect::<Vec<_>>();
+        checks.push(DiagnosticCheck::new(
+            "renderingOverrides",
+            Ok(if overrides.is_empty() {
+                "None set".into()
+            } else {
+                overrides.join(", ")
+            }),
+        ));
+    }
+    #[cfg(not(target_os = "linux"))]
+    drop(webview);
+
+    checks
+}
+
+/// The environment variable options are read from when they aren't passed as an argument.
+pub const OPTIONS_ENV_VAR: &str = "WEBVIEW_OPTIONS";
+
+/// Picks the options JSON to use, preferring the command line argument over the
+/// `WEBVIEW_OPTIONS` environment variable so large options don't have to show up in process listings.
+pub fn options_json(arg: Option<String>) -> Option<String> {
+    arg.or_else(|| env::var(OPTIONS_ENV_VAR).ok())
+}
+
+/// Validates options JSON against the `Options` schema, returning every problem found.
+///
+/// Unlike deserializing, unknown top level fields are reported so typos don't go unnoticed.
+pub fn check_options(json: &str) -> Vec<OptionsError> {
+    let value = match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(value) => value,
+        Err(err) => {
+            return vec![OptionsError {
+                field: None,
+                message: err.to_string(),
+            }]
+        }
+    };
+
+    let mut errors = Vec::new();
+    if let (Some(fields), Some(object)) = (
+        value.as_object(),
+        schemars::schema_for!(Options).schema.object,
+    ) {
+        for field in fields.keys() {
+            if !object.properties.contains_key(field) {
+                errors.push(OptionsError {
+                    field: Some(field.clone()),
+                    message: format!("unknown field `{}`", field),
+                });
+            }
+        }
+    }
+    match serde_json::from_value::<Options>(value) {
+        Ok(Options {
+            load: Some(Content::Html { charset, .. }),
+            ..
+        }) => {
+            if let Err(message) = html_encoding(&charset) {
+                errors.push(OptionsError {
+                    field: Some("load".into()),
+                    message,
+                });
+            }
+        }
+        Ok(_) => {}
+        Err(err) => errors.push(OptionsError {
+            field: None,
+            message: err.to_string(),
+        }),
+    }
+    errors
+}
+
+/// Explains why a window can't be opened on this machine, if it's obviously headless.
+#[cfg(target_os = "linux")]
+fn missing_display() -> Option<String> {
+    if env::var_os("DISPLAY").is_none() && env::var_os("WAYLAND_DISPLAY").is_none() {
+        Some(
+            "No display available: neither `DISPLAY` nor `WAYLAND_DISPLAY` is set. \
+             Run under a display server or a virtual one such as `xvfb-run`."
+                .into(),
+        )
+    } else {
+        None
+    }
+}
+
+/// Reports a failure to start directly to the client, since the output thread isn't running yet.
+fn startup_error(message: String) -> wry::Error {
+    let notifications = [
+        Notification::Error {
+            message: message.clone(),
+        },
+        Notification::Closed {
+            reason: CloseReason::Error,
+        },
+    ];
+    let mut stdout = std::io::stdout().lock();
+    for notification in notifications {
+        if let Ok(json) = serde_json::to_string(&Message::Notification(notification)) {
+            let _ = writeln!(stdout, "{}", json).and_then(|_| stdout.flush());
+        }
+    }
+    wry::Error::Io(std::io::Error::other(message))
+}
+
+/// Runs the webview, reading requests from stdin and writing messages to stdout until it closes.
+///
+/// It must be called on the main thread, use `run_on_any_thread` to run it elsewhere.
+pub fn run(webview_options: Options) -> wry::Result<()> {
+    run_with(webview_options, false)
+}
+
+/// Runs the webview like `run`, but from any thread, e.g. when the main thread is already running
+/// another runtime's event loop. Only one webview can run per process.
+///
+/// Platform-specific:
+/// - Linux: GTK must not be used from any other thread.
+/// - macOS: Unsupported, the event loop can only run on the main thread and an error is returned.
+pub fn run_on_any_thread(webview_options: Options) -> wry::Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        let _ = webview_options;
+        Err(startup_error(
+            "The webview can only run on the main thread on macOS".into(),
+        ))
+    }
+    #[cfg(not(target_os = "macos"))]
+    run_with(webview_options, true)
+}
+
+fn run_with(webview_options: Options, any_thread: bool) -> wry::Result<()> {
+    info!("Starting webview with options: {:?}", webview_options);
+    // Linux sets the header itself from the webview's preferred languages.
+    #[cfg(not(target_os = "linux"))]
+    let webview_options = Options {
+        default_headers: with_language(
+            webview_options.default_headers,
+            webview_options.language.as_deref(),
+        ),
+        ..webview_options
+    };
+    let webview_options = Options {
+        default_headers: with_referer(
+            webview_options.default_headers,
+            webview_options.referer.as_deref(),
+        ),
+        ..webview_options
+    };
+
+    // These mutexes are used to store the html, its charset and origin if the webview is created with html.
+    // The html and charset mutexes are needed to provide a value to the custom protocol and origin is needed
+    // as a fallback if `load_html` is called without an origin.
+    let html_mutex = Arc::new(Mutex::new("".to_string()));
+    let origin_mutex = Arc::new(Mutex::new(default_origin().to_string()));
+    let charset_mutex = Arc::new(Mutex::new(encoding_rs::UTF_8));
+    // When a directory is being served the custom protocol resolves files from it instead of
+    // returning the html.
+    let directory_mutex = Arc::new(Mutex::new(None::<PathBuf>));
+
+    let (tx, from_webview) = mpsc::channel::<Message>();
+    if webview_options.logs_over_rpc {
+        *RPC_LOG_SINK.lock() = Some(tx.clone());
+    }
+    let (to_eventloop, rx) =
+        mpsc::sync_channel::<Request>(webview_options.max_queued_requests.max(1));
+
+    #[cfg(target_os = "linux")]
+    if let Some(message) = missing_display() {
+        return Err(startup_error(message));
+    }
+    // The taskbar reads the id when the window's created, so it has to be set before then.
+    #[cfg(target_os = "windows")]
+    if let Some(id) = &webview_options.app_user_model_id {
+        use windows::core::HSTRING;
+        use windows::Win32::UI::Shell::SetCurrentProcessExplicitAppUserModelID;
+
+        // SAFETY: The id is a valid null terminated wide string for the duration of the call.
+        if let Err(err) = unsafe { SetCurrentProcessExplicitAppUserModelID(&HSTRING::from(id)) } {
+            error!("Failed to set the AppUserModelID: {:?}", err);
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    if webview_options.app_user_model_id.is_some() {
+        error!("Ignoring `appUserModelId` option, it's only supported on Windows");
+    }
+    // Initializing the platform's windowing backend panics on failure.
+    let event_loop = std::panic::catch_unwind(|| {
+        #[cfg_attr(target_os = "macos", allow(unused_mut))]
+        let mut event_loop_builder = EventLoopBuilder::<UserEvent>::with_user_event();
+        #[cfg(target_os = "linux")]
+        {
+            use tao::platform::unix::EventLoopBuilderExtUnix;
+            event_loop_builder.with_any_thread(any_thread);
+        }
+        #[cfg(target_os = "windows")]
+        {
+            use tao::platform::windows::EventLoopBuilderExtWindows;
+            event_loop_builder.with_any_thread(any_thread);
+        }
+        #[cfg(target_os = "macos")]
+        let _ = any_thread;
+        event_loop_builder.build()
+    })
+    .map_err(|_| {
+        startup_error(
+            "Failed to initialize the windowing system, check that a display server or \
+             compositor is running and accessible."
+                .into(),
+        )
+    })?;
+    #[cfg(target_os = "linux")]
+    let wayland = {
+        use tao::platform::unix::EventLoopWindowTargetExtUnix;
+        event_loop.is_wayland()
+    };
+    // GTK only applies the type hint before the window's shown, so it's shown once it's been set.
+    let set_type_hint = cfg!(target_os = "linux") && webview_options.window_type.is_some();
+    let mut window_builder = WindowBuilder::new()
+        .with_title(webview_options.title.clone())
+        .with_visible(!webview_options.show_on_first_paint && !set_type_hint)
+        .with_transparent(webview_options.transparent)
+        .with_decorations(webview_options.decorations)
+        .with_closable(webview_options.closable)
+        .with_theme(webview_options.theme.map(Theme::to_tao))
+        .with_always_on_bottom(webview_options.always_on_bottom);
+    if let Some(traffic_lights) = &webview_options.traffic_lights {
+        #[cfg(target_os = "macos")]
+        {
+            use tao::platform::macos::WindowBuilderExtMacOS;
+            window_builder = window_builder.with_titlebar_buttons_hidden(!traffic_lights.visible);
+            if let Some(inset) = &traffic_lights.inset {
+                window_builder = window_builder
+                    .with_traffic_light_inset(dpi::LogicalPosition::new(inset.x, inset.y));
+            }
+        }
+        #[cfg(not(target_os = "macos"))]
+        {
+            let _ = traffic_lights;
+            error!("Ignoring `trafficLights` option, it's only supported on macOS");
+        }
+    }
+
+    // The size the window was created or last set with, used until it's mapped and reports its
+    // actual size. It's unknown for maximized and fullscreen windows.
+    let mut unmapped_inner_size = match &webview_options.size {
+        Some(WindowSize::Size(Size { width, height })) => Some((*width, *height)),
+        _ => None,
+    };
+
+    match webview_options.size {
+        Some(WindowSize::States(WindowSizeStates::Maximized)) => {
+            window_builder = window_builder.with_maximized(true)
+        }
+        Some(WindowSize::States(WindowSizeStates::Fullscreen)) => {
+            window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)))
+        }
+        Some(WindowSize::Size(Size { width, height })) => {
+            window_builder = window_builder
+                .with_inner_size(dpi::Size::Logical(dpi::LogicalSize::new(width, height)))
+        }
+        // The size is set first so it's what the window is restored to.
+        Some(WindowSize::StateWithSize(WindowSizeStateWithSize {
+            state,
+            width,
+            height,
+        })) => {
+            window_builder = window_builder
+                .with_inner_size(dpi::Size::Logical(dpi::LogicalSize::new(width, height)));
+            window_builder = match state {
+                WindowSizeStates::Maximized => window_builder.with_maximized(true),
+                WindowSizeStates::Fullscreen => {
+                    window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)))
+                }
+            };
+        }
+        None => (),
+    }
+    match (&webview_options.position, &webview_options.anchor) {
+        (Some(_), Some(_)) => error!("Ignoring `position` option, `anchor` takes precedence"),
+        (Some(position), None) => {
+            window_builder = window_builder.with_position(dpi::Position::Logical(
+                dpi::LogicalPosition::new(position.x, position.y),
+            ))
+        }
+        _ => (),
+    }
+    let window = window_builder
+        .build(&event_loop)
+        .map_err(|err| startup_error(format!("Failed to create a window: {}", err)))?;
+
+    #[cfg(target_os = "linux")]
+    if let Some(window_type) = webview_options.window_type {
+        use gtk::gdk::WindowTypeHint;
+        use gtk::prelude::GtkWindowExt;
+        use tao::platform::unix::WindowExtUnix;
+
+        window.gtk_window().set_type_hint(match window_type {
+            WindowType::Normal => WindowTypeHint::Normal,
+            WindowType::Utility => WindowTypeHint::Utility,
+            WindowType::Dialog => WindowTypeHint::Dialog,
+        });
+        if !webview_options.show_on_first_paint {
+            window.set_visible(true);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if webview_options.window_type.is_some() {
+        error!("Ignoring `windowType` option, it's only supported on Linux");
+    }
+    #[cfg(target_os = "linux")]
+    if webview_options.transparent {
+        use tao::platform::unix::WindowExtUnix;
+
+        check_transparency(window.gtk_window());
+        // The webview is added to this box, which has to let the window's cleared background
+        // through as well.
+        if let Some(vbox) = window.default_vbox() {
+            gtk::prelude::WidgetExt::set_app_paintable(vbox, true);
+        }
+    }
+
+    if let Some(anchor) = &webview_options.anchor {
+        match window
+            .current_monitor()
+            .or_else(|| window.primary_monitor())
+        {
+            Some(monitor) => {
+                let monitor_position = monitor.position().to_logical::<f64>(monitor.scale_factor());
+                let monitor_size = monitor.size().to_logical::<f64>(monitor.scale_factor());
+                let size = window.outer_size().to_logical::<f64>(window.scale_factor());
+                let (x, y) = anchored_position(
+                    anchor,
+                    (size.width, size.height),
+                    &Rect {
+                        x: monitor_position.x,
+                        y: monitor_position.y,
+                        width: monitor_size.width,
+                        height: monitor_size.height,
+                    },
+                );
+                window.set_outer_position(dpi::LogicalPosition::new(x, y));
+            }
+            None => error!("Ignoring `anchor` option, no monitor was found"),
+        }
+    }
+
+    let html_mutex_init = html_mutex.clone();
+    let charset_mutex_init = charset_mutex.clone();
+    let directory_mutex_init = directory_mutex.clone();
+    let protocol_headers = Arc::new(parse_headers(webview_options.protocol_headers));
+    let load_html_headers = protocol_headers.clone();
+    let content_security_policy = webview_options
+        .content_security_policy
+        .map(Arc::<str>::from);
+    let load_html_policy = content_security_policy.clone();
+    let manifest = webview_options
+        .manifest
+        .as_ref()
+        .map(|manifest| manifest.to_string().into_bytes());
+    let loads_content = webview_options.load.is_some();
+    // The url last loaded with the default headers, whose navigation is let through rather
+    // than restarted with them.
+    let navigated_with_headers = Arc::new(Mutex::new(None::<String>));
+    let profile_dir = match webview_options.ephemeral_profile {
+        true if webview_options.incognito => {
+            error!("Ignoring `ephemeralProfile` option, it has no effect with `incognito`");
+            None
+        }
+        true => Some(create_profile_dir()?),
+        false => None,
+    };
+    let mut web_context = profile_dir
+        .as_ref()
+        .map(|dir| WebContext::new(Some(dir.clone())));
+    let new_webview_builder = match web_context.as_mut() {
+        Some(web_context) => WebViewBuilder::with_web_context(web_context),
+        None => WebViewBuilder::new(),
+    };
+    let mut splash = None;
+    if webview_options.splash_html.is_some()
+        && !matches!(webview_options.load, Some(Content::Url { .. }))
+    {
+        error!("Ignoring `splashHtml` option, it's only shown when loading a url");
+    }
+    let mut webview_builder = match webview_options.load {
+        // The splash is loaded first, the url is loaded once it has and replaces it when it's ready.
+        Some(Content::Url { url, headers }) if webview_options.splash_html.is_some() => {
+            *html_mutex.lock() = webview_options.splash_html.unwrap_or_default();
+            splash = Some(Splash::Showing { url, headers });
+            new_webview_builder.with_url(format!("load-html://{}", origin_mutex.lock()))
+        }
+        Some(Content::Url { url, headers }) => {
+            if !webview_options.default_headers.is_empty() {
+                *navigated_with_headers.lock() = Some(url.clone());
+            }
+            let mut webview_builder = new_webview_builder.with_url(url);
+            if let Some(headers) = with_default_headers(&webview_options.default_headers, headers) {
+                webview_builder = webview_builder.with_headers(parse_headers(headers));
+            }
+            webview_builder
+        }
+        Some(Content::Html {
+            html,
+            origin,
+            charset,
+        }) => {
+            origin_mutex.lock().clone_from(&origin);
+            *html_mutex.lock() = html;
+            *charset_mutex.lock() = html_encoding(&charset).map_err(startup_error)?;
+            new_webview_builder.with_url(format!("load-html://{}", origin))
+        }
+        Some(Content::Directory { path, entry }) => {
+            *directory_mutex.lock() = Some(PathBuf::from(path));
+            new_webview_builder.with_url(format!(
+                "load-html://{}/{}",
+                origin_mutex.lock(),
+                entry.trim_start_matches('/')
+            ))
+        }
+        None => new_webview_builder,
+    }
+    .with_custom_protocol("load-html".into(), move |_id, req| {
+        let response = match (directory_mutex_init.lock().as_deref(), &manifest) {
+            (_, Some(manifest)) if req.uri().path() == MANIFEST_PATH => serve_manifest(manifest),
+            (Some(root), _) => serve_directory(root, req.uri().path()),
+            (None, _) => serve_html(&html_mutex_init.lock(), *charset_mutex_init.lock()),
+        };
+        with_content_security_policy(
+            with_protocol_headers(response, &load_html_headers),
+            load_html_policy.as_deref(),
+        )
+    })
+    .with_transparent(webview_options.transparent)
+    .with_autoplay(match webview_options.autoplay_policy {
+        Some(AutoplayPolicy::Allow | AutoplayPolicy::AllowMuted) => true,
+        Some(AutoplayPolicy::Deny) => false,
+        None => webview_options.autoplay,
+    })
+    .with_incognito(webview_options.incognito)
+    .with_clipboard(webview_options.clipboard)
+    .with_focused(webview_options.focused)
+    .with_devtools(webview_options.devtools)
+    .with_accept_first_mouse(webview_options.accept_first_mouse);
+    // The IPC handler is always installed since injected scripts report back through it, the
+    // page's own messages are only forwarded when `ipc` is enabled.
+    let ipc_tx = tx.clone();
+    let ipc_enabled = webview_options.ipc;
+    let page_proxy = event_loop.create_proxy();
+    let next_seq = AtomicU64::new(0);
+    webview_builder = webview_builder.with_ipc_handler(move |message| {
+        let body = message.body();
+        if let Some(message) = PageMessage::parse(body) {
+            let _ = page_proxy.send_event(UserEvent::Page(message));
+        } else if ipc_enabled {
+            ipc_tx
+                .send(Message::Notification(Notification::Ipc {
+                    message: body.to_string(),
+                    seq: next_seq.fetch_add(1, Ordering::Relaxed),
+                    rpc_id: ipc_rpc_id(body),
+                }))
+                .unwrap()
+        }
+    });
+    let client_scheme = webview_options
+        .client_protocol
+        .as_ref()
+        .map(|protocol| protocol.scheme.clone());
+    for (scheme, root) in webview_options.custom_protocols {
+        if scheme == "load-html" || scheme == FONT_SCHEME || client_scheme.as_ref() == Some(&scheme)
+        {
+            error!(
+                scheme,
+                "Ignoring custom protocol, the scheme is already in use"
+            );
+            continue;
+        }
+        let root = PathBuf::from(root);
+        let protocol_headers = protocol_headers.clone();
+        let content_security_policy = content_security_policy.clone();
+        webview_builder = webview_builder.with_custom_protocol(scheme, move |_id, req| {
+            with_content_security_policy(
+                with_protocol_headers(serve_directory(&root, req.uri().path()), &protocol_headers),
+                content_security_policy.as_deref(),
+            )
+        });
+    }
+
+    if !webview_options.fonts.is_empty() {
+        // Windows serves custom protocols from a subdomain of an http(s) origin.
+        #[cfg(target_os = "windows")]
+        let font_origin = format!(
+            "{}://{}.localhost",
+            if webview_options.https_scheme {
+                "https"
+            } else {
+                "http"
+            },
+            FONT_SCHEME
+        );
+        #[cfg(not(target_os = "windows"))]
+        let font_origin = format!("{}://localhost", FONT_SCHEME);
+        let mut fonts = Vec::new();
+        let mut css = String::new();
+        for font in &webview_options.fonts {
+            match load_font(font) {
+                Ok(bytes) => {
+                    css.push_str(&font_face_css(
+                        font,
+                        &format!("{}/{}", font_origin, fonts.len()),
+                    ));
+                    fonts.push(bytes);
+                }
+                Err(err) => error!(family = font.family, "Ignoring font: {}", err),
+            }
+        }
+        webview_builder = webview_builder
+            .with_initialization_script(stylesheet_script("__webview-fonts", &css))
+            .with_custom_protocol(FONT_SCHEME.into(), move |_id, req| {
+                let font = req
+                    .uri()
+                    .path()
+                    .trim_start_matches('/')
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| fonts.get(index));
+                match font {
+                    // Fonts are fetched with CORS, so pages from other origins need to be allowed.
+                    Some(font) => HttpResponse::builder()
+                        .header("Content-Type", font_mime_type(font))
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Cow::Owned(font.clone()))
+                        .unwrap(),
+                    None => HttpResponse::builder()
+                        .status(404)
+                        .body(Cow::Borrowed(&[][..]))
+                        .unwrap(),
+                }
+            });
+    }
+
+    // Client protocol requests waiting on a `protocolResponse`, keyed by the id sent in the
+    // `protocolRequest` notification.
+    let pending_protocol_requests =
+        Arc::new(Mutex::new(HashMap::<u64, RequestAsyncResponder>::new()));
+    if let Some(protocol) =
+        webview_options
+            .client_protocol
+            .filter(|protocol| match protocol.scheme.as_str() {
+                "load-html" => {
+                    error!("Ignoring `clientProtocol`, the `load-html` scheme is reserved");
+                    false
+                }
+                FONT_SCHEME => {
+                    error!("Ignoring `clientProtocol`, the `webview-font` scheme is reserved");
+                    false
+                }
+                _ => true,
+            })
+    {
+        let protocol_tx = tx.clone();
+        let timeout = Duration::from_millis(protocol.timeout_ms);
+        let next_request_id = Arc::new(AtomicU64::new(0));
+        // Fail requests the client doesn't answer in time.
+        let timeouts = spawn_timeouts({
+            let pending_protocol_requests = pending_protocol_requests.clone();
+            move |request_id| {
+                if let Some(responder) = pending_protocol_requests.lock().remove(&request_id) {
+                    warn!(request_id, "Protocol request timed out");
+                    responder.respond(
+                        HttpResponse::builder()
+                            .status(504)
+                            .body(Cow::Borrowed(&[] as &[u8]))
+                            .unwrap(),
+                    );
+                }
+            }
+        });
+        let pending_protocol_requests = pending_protocol_requests.clone();
+        webview_builder = webview_builder.with_asynchronous_custom_protocol(
+            protocol.scheme,
+            move |_id, request, responder| {
+                let request_id = next_request_id.fetch_add(1, Ordering::Relaxed);
+                debug!(request_id, uri = %request.uri(), "Forwarding protocol request to client");
+                pending_protocol_requests
+                    .lock()
+                    .insert(request_id, responder);
+                protocol_tx
+                    .send(Message::Notification(protocol_request_notification(
+                        request_id, &request,
+                    )))
+                    .unwrap();
+                let _ = timeouts.send((Instant::now() + timeout, request_id));
+            },
+        );
+    }
+    if webview_options.lock_title {
+        let title_proxy = event_loop.create_proxy();
+        webview_builder = webview_builder.with_document_title_changed_handler(move |title| {
+            let _ = title_proxy.send_event(UserEvent::DocumentTitleChanged(title));
+        });
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use wry::WebViewBuilderExtWindows;
+        webview_builder = webview_builder.with_https_scheme(webview_options.https_scheme);
+        if let Some(args) = &webview_options.additional_browser_args {
+            webview_builder = webview_builder.with_additional_browser_args(args);
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    if webview_options.additional_browser_args.is_some() {
+        error!("Ignoring `additionalBrowserArgs` option, it's only supported on Windows");
+    }
+    let navigation_chain = Arc::new(Mutex::new(NavigationChain::default()));
+    let restart_with_headers = !webview_options.default_headers.is_empty();
+    {
+        let navigation_proxy = event_loop.create_proxy();
+        let navigated_with_headers = navigated_with_headers.clone();
+        let navigation_chain = navigation_chain.clone();
+        let navigation_tx = tx.clone();
+        let url_filter = webview_options.url_filter.unwrap_or_default();
+        webview_builder = webview_builder.with_navigation_handler(move |url| {
+            if !url_filter.allows(&url) {
+                info!(url, "Blocked navigation");
+                let _ =
+                    navigation_tx.send(Message::Notification(Notification::RequestBlocked { url }));
+                return false;
+            }
+            navigation_chain.lock().visit(&url);
+            if !restart_with_headers {
+                return true;
+            }
+            let mut navigated = navigated_with_headers.lock();
+            if navigated.as_deref() == Some(url.as_str()) {
+                *navigated = None;
+                return true;
+            }
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return true;
+            }
+            debug!(url, "Restarting navigation with the default headers");
+            let _ = navigation_proxy.send_event(UserEvent::NavigateWithHeaders(url));
+            false
+        });
+    }
+    let page_load_proxy = event_loop.create_proxy();
+    let page_load_chain = navigation_chain.clone();
+    webview_builder = webview_builder.with_on_page_load_handler(move |event, url| {
+        let _ = page_load_proxy.send_event(match event {
+            wry::PageLoadEvent::Started => UserEvent::PageLoadStarted,
+            wry::PageLoadEvent::Finished => {
+                page_load_chain.lock().finish(&url);
+                UserEvent::PageLoadFinished
+            }
+        });
+    });
+    if webview_options.show_on_first_paint {
+        webview_builder = webview_builder.with_initialization_script(first_paint_script());
+    }
+    webview_builder = webview_builder.with_initialization_script(fullscreen_change_script());
+    if webview_options.ipc {
+        webview_builder = webview_builder.with_initialization_script(PAGE_RPC_SCRIPT);
+    }
+    if webview_options.emulate_touch {
+        webview_builder = webview_builder.with_initialization_script(touch_emulation_script(true));
+    }
+    if webview_options.drag_region_height > 0.0 {
+        webview_builder = webview_builder
+            .with_initialization_script(drag_region_script(webview_options.drag_region_height));
+    }
+    if webview_options.report_csp_violations {
+        webview_builder = webview_builder.with_initialization_script(csp_violation_script());
+    }
+    if let Some(policy) = webview_options.referrer_policy {
+        webview_builder =
+            webview_builder.with_initialization_script(referrer_policy_script(policy));
+    }
+    let mut scroll_behavior = ScrollBehavior {
+        smooth_scrolling: webview_options.smooth_scrolling,
+        overscroll: webview_options.overscroll,
+        scrollbars_hidden: !webview_options.scrollbars_visible,
+    };
+    if scroll_behavior != ScrollBehavior::default() {
+        webview_builder = webview_builder.with_initialization_script(scroll_behavior.script());
+    }
+    if webview_options.pause_animations_when_hidden {
+        webview_builder = webview_builder.with_initialization_script(PAUSE_ANIMATIONS_SCRIPT);
+    }
+    if webview_options.transparent_html {
+        webview_builder = webview_builder.with_initialization_script(TRANSPARENT_HTML_SCRIPT);
+    }
+    if let Some(manifest) = &webview_options.manifest {
+        let theme_color = manifest.get("theme_color").and_then(|color| color.as_str());
+        webview_builder = webview_builder.with_initialization_script(manifest_script(theme_color));
+    }
+    if webview_options.autoplay_policy == Some(AutoplayPolicy::AllowMuted) {
+        webview_builder = webview_builder.with_initialization_script(AUTOPLAY_MUTED_SCRIPT);
+    }
+    if webview_options.disable_zoom {
+        webview_builder = webview_builder
+            .with_hotkeys_zoom(false)
+            .with_initialization_script(DISABLE_ZOOM_SCRIPT);
+    }
+    if let Some(scroll_restoration) = webview_options.scroll_restoration {
+        webview_builder = webview_builder.with_initialization_script(format!(
+            "history.scrollRestoration = '{}';",
+            scroll_restoration.as_str()
+        ));
+    }
+    if let Some(initialization_script) = webview_options.initialization_script {
+        webview_builder =
+            webview_builder.with_initialization_script(initialization_script.as_str());
+    }
+    if let Some(user_agent) = webview_options.user_agent {
+        webview_builder = webview_builder.with_user_agent(user_agent.as_str());
+    }
+    #[cfg(not(target_os = "linux"))]
+    let webview = webview_builder.build(&window);
+
+    #[cfg(target_os = "linux")]
+    let webview = {
+        use tao::platform::unix::WindowExtUnix;
+        use wry::WebViewBuilderExtUnix;
+        let vbox = window.default_vbox().unwrap();
+        webview_builder.build_gtk(vbox)
+    };
+    let webview =
+        webview.map_err(|err| startup_error(format!("Failed to create the webview: {}", err)))?;
+
+    if let Some(smooth_scrolling) = scroll_behavior.smooth_scrolling {
+        set_smooth_scrolling(&webview, smooth_scrolling);
+    }
+    // The scroll behavior set with `setScrollBehavior`, which replaces the one added on creation.
+    // WebKitGTK can add it before later pages load, elsewhere it's applied after they have.
+    #[cfg(target_os = "linux")]
+    let mut scroll_user_script: Option<webkit2gtk::UserScript> = None;
+    #[cfg(not(target_os = "linux"))]
+    let mut scroll_behavior_set = false;
+    // The drag region set with `setDragRegionHeight`, kept the same way as the scroll behavior.
+    let mut drag_region_height = webview_options.drag_region_height;
+    #[cfg(target_os = "linux")]
+    let mut drag_region_user_script: Option<webkit2gtk::UserScript> = None;
+    #[cfg(not(target_os = "linux"))]
+    let mut drag_region_set = false;
+    // Touch emulation set with `setTouchEmulation`, kept the same way as the scroll behavior.
+    let mut touch_emulation = webview_options.emulate_touch;
+    #[cfg(target_os = "linux")]
+    let mut touch_emulation_user_script: Option<webkit2gtk::UserScript> = None;
+    #[cfg(not(target_os = "linux"))]
+    let mut touch_emulation_set = false;
+    match webview_options.text_zoom {
+        Some(factor) if factor <= 0.0 => {
+            error!("Ignoring `textZoom` option, it must be greater than 0")
+        }
+        Some(factor) => {
+            if let Err(message) = set_text_zoom(&webview, factor) {
+                error!("Ignoring `textZoom` option: {}", message);
+            }
+        }
+        None => (),
+    }
+    if webview_options.open_devtools_on_start {
+        #[cfg(feature = "devtools")]
+        if webview_options.devtools {
+            webview.open_devtools();
+        } else {
+            error!("Ignoring `openDevtoolsOnStart` option, `devtools` isn't enabled");
+        }
+        #[cfg(not(feature = "devtools"))]
+        error!("Ignoring `openDevtoolsOnStart` option, the binary was built without the `devtools` feature");
+    }
+
+    // Permission requests waiting on a decision from the client, keyed by the id sent in the
+    // `PermissionRequested` notification.
+    #[cfg(target_os = "linux")]
+    let pending_permissions = Rc::new(RefCell::new(
+        HashMap::<u64, webkit2gtk::PermissionRequest>::new(),
+    ));
+
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{NetworkError, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        if let (Some(language), Some(context)) =
+            (&webview_options.language, webview.webview().context())
+        {
+            use webkit2gtk::WebContextExt;
+            context.set_preferred_languages(&[language.as_str()]);
+        }
+        let load_failed_tx = tx.clone();
+        let suppress_error_pages = webview_options.suppress_error_pages;
+        let error_page_html = webview_options.error_page_html.clone().unwrap_or_default();
+        webview
+            .webview()
+            .connect_load_failed(move |view, _, url, err| {
+                // Cancelled loads are routine, e.g. when navigating away before a page finishes.
+                if err.matches(NetworkError::Cancelled) {
+                    return false;
+                }
+                warn!(url, "Failed to load page: {}", err);
+                load_failed_tx
+                    .send(Message::Notification(Notification::LoadFailed {
+                        url: url.to_string(),
+                        message: err.to_string(),
+                    }))
+                    .unwrap();
+                if suppress_error_pages {
+                    view.load_alternate_html(&error_page_html, url, None);
+                }
+                suppress_error_pages
+            });
+    }
+    #[cfg(not(target_os = "linux"))]
+    if webview_options.suppress_error_pages {
+        error!("Ignoring `suppressErrorPages` option, it's only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{WebProcessTerminationReason, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        let proxy = event_loop.create_proxy();
+        webview
+            .webview()
+            .connect_web_process_terminated(move |_, reason| {
+                // Only `recreateWebview` terminates the process, which starts a new one itself.
+                if reason == WebProcessTerminationReason::TerminatedByApi {
+                    return;
+                }
+                error!(?reason, "Web process terminated");
+                let reason = match reason {
+                    WebProcessTerminationReason::Crashed => CloseReason::Crashed,
+                    _ => CloseReason::Error,
+                };
+                let _ = proxy.send_event(UserEvent::WebProcessTerminated(reason));
+            });
+    }
+
+    // Kept until the process exits, since `event_loop.run` never returns.
+    #[cfg(target_os = "linux")]
+    let _memory_monitor = (webview_options.report_memory_pressure
+        || webview_options.release_memory_on_pressure)
+        .then(|| {
+            use gio::prelude::MemoryMonitorExt;
+
+            let memory_monitor = gio::MemoryMonitor::dup_default();
+            let proxy = event_loop.create_proxy();
+            memory_monitor.connect_low_memory_warning(move |_, level| {
+                let level = match level {
+                    gio::MemoryMonitorWarningLevel::Low => MemoryPressureLevel::Low,
+                    gio::MemoryMonitorWarningLevel::Medium => MemoryPressureLevel::Medium,
+                    _ => MemoryPressureLevel::Critical,
+                };
+                let _ = proxy.send_event(UserEvent::MemoryPressure(level));
+            });
+            memory_monitor
+        });
+    #[cfg(not(target_os = "linux"))]
+    if webview_options.report_memory_pressure || webview_options.release_memory_on_pressure {
+        error!("Ignoring memory pressure options, they're only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{PermissionRequestExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        let policy = webview_options.permissions;
+        let pending_permissions = pending_permissions.clone();
+        let permission_tx = tx.clone();
+        let next_request_id = Cell::new(0);
+        webview
+            .webview()
+            .connect_permission_request(move |view, request| {
+                let kind = permission_kind(request);
+                debug!(?kind, ?policy, "Permission requested");
+                match policy {
+                    PermissionPolicy::Platform => false,
+                    PermissionPolicy::Allow => {
+                        request.allow();
+                        true
+                    }
+                    PermissionPolicy::Deny => {
+                        request.deny();
+                        true
+                    }
+                    PermissionPolicy::Ask => {
+                        let request_id = next_request_id.get();
+                        next_request_id.set(request_id + 1);
+                        pending_permissions
+                            .borrow_mut()
+                            .insert(request_id, request.clone());
+                        let origin = view
+                            .uri()
+                            .map(|uri| webkit2gtk::SecurityOrigin::for_uri(&uri).to_str().into())
+                            .unwrap_or_default();
+                        permission_tx
+                            .send(Message::Notification(Notification::PermissionRequested {
+                                request_id,
+                                origin,
+                                kind,
+                            }))
+                            .unwrap();
+                        true
+                    }
+                }
+            });
+    }
+    #[cfg(not(target_os = "linux"))]
+    if webview_options.permissions != PermissionPolicy::Platform {
+        error!("Ignoring `permissions` option, only `platform` is supported on this platform");
+    }
+
+    let notify_tx = tx.clone();
+    let notify = move |notification: Notification| {
+        debug!(notification = ?notification, "Sending notification to client");
+        notify_tx.send(Message::Notification(notification)).unwrap();
+    };
+
+    // When tracing, the time each request was received so the response can report how long it took.
+    let trace_rpc = webview_options.trace_rpc;
+    let request_times = Arc::new(Mutex::new(HashMap::<i64, Instant>::new()));
+
+    let res_tx = tx.clone();
+    let res_request_times = request_times.clone();
+    let res = move |response: Response| {
+        debug!(response = ?response, "Sending response to client");
+        if trace_rpc {
+            let elapsed = res_request_times
+                .lock()
+                .remove(&response.id())
+                .map(|received| received.elapsed());
+            info!(id = response.id(), ?elapsed, ?response, "Sending response");
+        }
+        res_tx.send(Message::Response(response)).unwrap();
+    };
+
+    // Handle messages from the webview to the client.
+    let closed_written = process_output(
+        std::io::stdout(),
+        from_webview,
+        webview_options
+            .output_deadline_ms
+            .map(|deadline_ms| OutputDeadline {
+                after: Duration::from_millis(deadline_ms),
+                exit: webview_options.exit_on_output_deadline,
+            }),
+    );
+
+    // Handle messages from the client to the webview.
+    process_input(
+        BufReader::new(std::io::stdin()),
+        RequestQueue {
+            sender: to_eventloop,
+            overflow: webview_options.queue_overflow,
+            responses: tx.clone(),
+            echo_requests: webview_options.debug_echo_requests,
+        },
+    );
+
+    // Resize and move events arrive in bursts while the user drags the window, so they're
+    // coalesced before being sent to the client.
+    let throttle_interval = Duration::from_millis(webview_options.window_event_throttle_ms);
+    let mut resize_throttle = Throttle::<SizeWithScale>::new(throttle_interval);
+    let mut move_throttle = Throttle::<Position>::new(throttle_interval);
+
+    // Responses for requests waiting on a correlated script, keyed by request id.
+    let mut pending_evals = HashMap::<i64, EvalResponder>::new();
+    let find_state = Rc::new(RefCell::new(FindState::default()));
+    // WebKitGTK reports the result of each search step through the find controller's signals.
+    #[cfg(target_os = "linux")]
+    {
+        use webkit2gtk::{FindControllerExt, WebViewExt};
+        use wry::WebViewExtUnix;
+
+        if let Some(find_controller) = webview.webview().find_controller() {
+            let found_state = find_state.clone();
+            let found_tx = tx.clone();
+            find_controller.connect_found_text(move |_, matches| {
+                let active = found_state.borrow_mut().found(matches);
+                let _ = found_tx.send(Message::Notification(Notification::FindResult {
+                    matches,
+                    active,
+                }));
+            });
+            let failed_state = find_state.clone();
+            let failed_tx = tx.clone();
+            find_controller.connect_failed_to_find_text(move |_| {
+                failed_state.borrow_mut().found(0);
+                let _ = failed_tx.send(Message::Notification(Notification::FindResult {
+                    matches: 0,
+                    active: 0,
+                }));
+            });
+        }
+    }
+
+    let mut first_painted = false;
+    let mut webview_visible = true;
+    let mut follow_system_theme = webview_options.theme.is_none();
+    let mut modifiers = tao::keyboard::ModifiersState::empty();
+
+    // Regions of the window that stay interactive while the rest ignores the cursor, and when to
+    // next check which one the cursor's in.
+    let mut interactive_regions = Vec::<Rect>::new();
+    let mut cursor_poll_at: Option<Instant> = None;
+    let mut cursor_ignored = false;
+
+    // Constraints the window size is corrected to whenever it's resized.
+    let mut aspect_ratio = webview_options.aspect_ratio;
+    let mut resize_increments = webview_options
+        .resize_increments
+        .as_ref()
+        .map(|size| (size.width, size.height));
+    let mut animations_paused = false;
+
+    // The requests of the batch being handled, whose responses are collected until it's done.
+    let mut batch: Option<(i64, std::collections::VecDeque<Request>)> = None;
+    let batch_responses = RefCell::new(Vec::<Response>::new());
+
+    // `whenReady` scripts are held back while a page is loading, otherwise they'd run against the
+    // page being navigated away from. Loads are marked as started as soon as they're requested,
+    // since the page load handler only hears about them later.
+    let mut page_loading = loads_content;
+    let mut ready_scripts = Vec::<(i64, String)>::new();
+    // When each `whenReady` request times out if it's still waiting on the page.
+    let mut ready_deadlines = Vec::<(Instant, i64)>::new();
+    #[cfg(unix)]
+    if let Err(err) = forward_signals(event_loop.create_proxy()) {
+        error!("Failed to handle termination signals: {:?}", err);
+    }
+
+    // Scripts added with `addScript`, keyed by the id of the request that added them. WebKitGTK
+    // can add and remove user scripts at any time, elsewhere they're run after each page load.
+    #[cfg(target_os = "linux")]
+    let mut added_scripts = HashMap::<i64, webkit2gtk::UserScript>::new();
+    #[cfg(not(target_os = "linux"))]
+    let mut added_scripts = Vec::<(i64, String)>::new();
+
+    // Saving a file usually produces several file system events, so reloads are debounced.
+    let mut reload_at: Option<Instant> = None;
+
+    #[cfg(feature = "watch")]
+    let _watcher = webview_options.watch.as_deref().and_then(|path| {
+        info!(path, "Watching for file changes");
+        watch_directory(path, event_loop.create_proxy())
+            .map_err(|err| error!("Failed to watch {}: {:?}", path, err))
+            .ok()
+    });
+    #[cfg(not(feature = "watch"))]
+    if webview_options.watch.is_some() {
+        error!("Ignoring `watch` option, the binary was built without the `watch` feature");
+    }
+
+    event_loop.run(move |event, _, control_flow| {
+        // Wake up in time to send any latched resize or move events and to run pending reloads.
+        *control_flow = [
+            resize_throttle.deadline(),
+            move_throttle.deadline(),
+            reload_at,
+            cursor_poll_at,
+            ready_deadlines.iter().map(|(at, _)| *at).min(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .map_or(ControlFlow::Wait, ControlFlow::WaitUntil);
+
+        match event {
+            Event::LoopDestroyed => {
+                // Give the client a chance to hear why the webview closed before the process exits.
+                if closed_written.recv_timeout(CLOSED_WRITE_TIMEOUT).is_err() {
+                    warn!("Exiting before the closed notification was written");
+                }
+                if let Some(dir) = &profile_dir {
+                    // The context may still hold files in it open.
+                    drop(web_context.take());
+                    if let Err(err) = std::fs::remove_dir_all(dir) {
+                        error!(?err, dir = %dir.display(), "Failed to delete the ephemeral profile");
+                    }
+                }
+            }
+            // The webview was built before the loop started and requests are only read from here
+            // on, so the client can drive it as soon as it hears this.
+            Event::NewEvents(StartCause::Init) => {
+                info!("Webview initialized");
+                notify(Notification::Started {
+                    version: VERSION.into(),
+                    protocol_version: PROTOCOL_VERSION,
+                });
+            }
+            Event::UserEvent(UserEvent::FileChanged) => {
+                reload_at = Some(Instant::now() + Duration::from_millis(100));
+                *control_flow = ControlFlow::WaitUntil(reload_at.unwrap());
+            }
+            Event::UserEvent(UserEvent::DocumentTitleChanged(title)) => {
+                // Reverting the title fires this again with the window title, which is ignored.
+                let locked_title = window.title();
+                if title != locked_title {
+                    debug!(title, "Reverting page title change");
+                    if let Err(err) = webview.evaluate_script(&document_title_script(&locked_title))
+                    {
+                        error!("Failed to revert page title: {:?}", err);
+                    }
+                    notify(Notification::TitleOverridden { title });
+                }
+            }
+            Event::UserEvent(UserEvent::PageLoadStarted) => page_loading = true,
+            // The page still counts as loading until the url has replaced the splash.
+            Event::UserEvent(UserEvent::PageLoadFinished)
+                if matches!(splash, Some(Splash::Showing { .. })) =>
+            {
+                if let Some(Splash::Showing { url, headers }) = splash.take() {
+                    if !webview_options.default_headers.is_empty() {
+                        *navigated_with_headers.lock() = Some(url.clone());
+                    }
+                    let result =
+                        match with_default_headers(&webview_options.default_headers, headers) {
+                            Some(headers) => {
+                                webview.load_url_with_headers(&url, parse_headers(headers))
+                            }
+                            None => webview.load_url(&url),
+                        };
+                    match result {
+                        Ok(_) => splash = Some(Splash::Loading),
+                        Err(err) => error!("Failed to load {} behind the splash: {:?}", url, err),
+                    }
+                }
+            }
+            Event::UserEvent(UserEvent::PageLoadFinished) => {
+                page_loading = false;
+                if let Some(Splash::Loading) = splash.take() {
+                    info!("Page loaded, splash dismissed");
+                    notify(Notification::SplashDismissed);
+                }
+                if animations_paused {
+                    if let Err(err) = webview.evaluate_script(&animations_paused_script(true)) {
+                        error!("Failed to pause animations: {:?}", err);
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                if scroll_behavior_set {
+                    if let Err(err) = webview.evaluate_script(&scroll_behavior.script()) {
+                        error!("Failed to apply the scroll behavior: {:?}", err);
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                if drag_region_set {
+                    if let Err(err) =
+                        webview.evaluate_script(&drag_region_script(drag_region_height))
+                    {
+                        error!("Failed to apply the drag region: {:?}", err);
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                if touch_emulation_set {
+                    if let Err(err) =
+                        webview.evaluate_script(&touch_emulation_script(touch_emulation))
+                    {
+                        error!("Failed to apply touch emulation: {:?}", err);
+                    }
+                }
+                #[cfg(not(target_os = "linux"))]
+                for (_, script) in &added_scripts {
+                    if let Err(err) = webview.evaluate_script(script) {
+                        error!("Failed to run added script: {:?}", err);
+                    }
+                }
+                for (id, js) in ready_scripts.drain(..) {
+                    if let Some(response) = eval_correlated(
+                        &webview,
+                        &mut pending_evals,
+                        id,
+                        &when_ready_script(&js),
+                        move |result| json_result(id, result),
+                    ) {
+                        res(response);
+                    }
+                }
+            }
+            Event::UserEvent(UserEvent::NavigateWithHeaders(url)) => {
+                *navigated_with_headers.lock() = Some(url.clone());
+                let headers = parse_headers(webview_options.default_headers.clone());
+                match webview.load_url_with_headers(&url, headers) {
+                    Ok(_) => page_loading = true,
+                    Err(err) => error!("Failed to navigate to {}: {:?}", url, err),
+                }
+            }
+            Event::UserEvent(UserEvent::Page(PageMessage::EvalResult { id, result })) => {
+                match pending_evals.remove(&id) {
+                    Some(respond) => {
+                        if let Some(response) = respond(Ok(result)) {
+                            res(response);
+                        }
+                    }
+                    None => debug!(id, "Ignoring result for unknown script"),
+                }
+            }
+            Event::UserEvent(UserEvent::Page(PageMessage::EvalError { id, message })) => {
+                match pending_evals.remove(&id) {
+                    Some(respond) => {
+                        if let Some(response) = respond(Err(message)) {
+                            res(response);
+                        }
+                    }
+                    None => debug!(id, "Ignoring error for unknown script"),
+                }
+            }
+            Event::UserEvent(UserEvent::Page(PageMessage::StartDrag)) if drag_region_height > 0.0 => {
+                if let Err(err) = window.drag_window() {
+                    error!("Failed to drag the window: {:?}", err);
+                }
+            }
+            // Later navigations paint too, only the first one matters.
+            Event::UserEvent(UserEvent::Page(PageMessage::FirstPaint)) if !first_painted => {
+                first_painted = true;
+                info!("Page painted, showing window");
+                window.set_visible(true);
+                notify(Notification::FirstPaint);
+            }
+            Event::UserEvent(UserEvent::Page(PageMessage::FullscreenChanged { fullscreen })) => {
+                if webview_options.follow_page_fullscreen {
+                    window.set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)));
+                }
+                notify(Notification::PageFullscreenChanged { fullscreen });
+            }
+            Event::UserEvent(UserEvent::Page(PageMessage::CspViolation {
+                directive,
+                blocked_uri,
+                source_file,
+                line_number,
+                column_number,
+                sample,
+                enforced,
+            })) if webview_options.report_csp_violations => {
+                debug!(directive, blocked_uri, "Content security policy violated");
+                notify(Notification::CspViolation {
+                    directive,
+                    blocked_uri,
+                    source_file,
+                    line_number,
+                    column_number,
+                    sample,
+                    enforced,
+                });
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } if !webview_options.closable => {
+                info!("Blocked the user from closing the window");
+                notify(Notification::CloseBlocked);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                info!("Webview close requested");
+                notify(Notification::Closed {
+                    reason: CloseReason::UserClosed,
+                });
+                *control_flow = ControlFlow::Exit
+            }
+            Event::UserEvent(UserEvent::Signal(signal)) => {
+                info!(signal, "Received a termination signal, closing");
+                notify(Notification::Closed {
+                    reason: CloseReason::Signal,
+                });
+                *control_flow = ControlFlow::Exit
+            }
+            Event::UserEvent(UserEvent::MemoryPressure(level)) => {
+                warn!(?level, "The system is low on memory");
+                if webview_options.release_memory_on_pressure {
+                    release_memory(&webview, level);
+                }
+                if webview_options.report_memory_pressure {
+                    notify(Notification::MemoryPressure { level });
+                }
+            }
+            Event::UserEvent(UserEvent::WebProcessTerminated(reason)) => {
+                notify(Notification::Closed { reason });
+                *control_flow = ControlFlow::Exit
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                unmapped_inner_size = None;
+                let scale_factor = window.scale_factor();
+                let size = size.to_logical::<f64>(scale_factor);
+                // The window is resized again to the corrected size, which then passes unchanged.
+                let (width, height) =
+                    constrain_size((size.width, size.height), resize_increments, aspect_ratio);
+                if (width - size.width).abs() >= 1.0 || (height - size.height).abs() >= 1.0 {
+                    window.set_inner_size(dpi::LogicalSize::new(width, height));
+                }
+                let size = SizeWithScale {
+                    width,
+                    height,
+                    scale_factor,
+                };
+                if let Some(size) = resize_throttle.push(size, Instant::now()) {
+                    notify(Notification::Resized { size });
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Moved(position),
+                ..
+            } => {
+                let position = position.to_logical::<f64>(window.scale_factor());
+                let position = Position {
+                    x: position.x,
+                    y: position.y,
+                };
+                if let Some(position) = move_throttle.push(position, Instant::now()) {
+                    notify(Notification::Moved { position });
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => modifiers = state,
+            Event::WindowEvent {
+                event: WindowEvent::ThemeChanged(theme),
+                ..
+            } if follow_system_theme => notify(Notification::ThemeChanged {
+                theme: Theme::from_tao(theme),
+            }),
+            // Synthetic events are made up for keys already held when the window gains or loses
+            // focus, they aren't real presses.
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event,
+                        is_synthetic: false,
+                        ..
+                    },
+                ..
+            } if webview_options.forward_keyboard => {
+                let key = match &event.logical_key {
+                    tao::keyboard::Key::Character(character) => character.to_string(),
+                    key => format!("{:?}", key),
+                };
+                let code = event.physical_key.to_string();
+                let modifiers = KeyModifiers::from_tao(modifiers);
+                notify(match event.state {
+                    ElementState::Pressed => Notification::KeyDown {
+                        key,
+                        code,
+                        modifiers,
+                        repeat: event.repeat,
+                    },
+                    _ => Notification::KeyUp {
+                        key,
+                        code,
+                        modifiers,
+                        repeat: false,
+                    },
+                });
+            }
+            Event::WindowEvent {
+                event: WindowEvent::HoveredFile(path),
+                ..
+            } => notify(Notification::FileHovered {
+                path: path.to_string_lossy().into_owned(),
+            }),
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => notify(Notification::FileDropped {
+                path: path.to_string_lossy().into_owned(),
+            }),
+            Event::WindowEvent {
+                event: WindowEvent::HoveredFileCancelled,
+                ..
+            } => notify(Notification::FileHoverCancelled),
+            Event::MainEventsCleared => {
+                let now = Instant::now();
+                if let Some(size) = resize_throttle.flush(now) {
+                    notify(Notification::Resized { size });
+                }
+                if let Some(position) = move_throttle.flush(now) {
+                    notify(Notification::Moved { position });
+                }
+                if reload_at.is_some_and(|at| now >= at) {
+                    reload_at = None;
+                    info!("Reloading webview after file change");
+                    match webview.reload() {
+                        Ok(_) => {
+                            page_loading = true;
+                            notify(Notification::Reloaded {
+                                reason: ReloadReason::FileChange,
+                            })
+                        }
+                        Err(err) => error!("Failed to reload webview: {:?}", err),
+                    }
+                }
+                let (expired, waiting) = ready_deadlines
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|(at, _)| now >= *at);
+                ready_deadlines = waiting;
+                for (_, id) in expired {
+                    let queued = ready_scripts.len();
+                    ready_scripts.retain(|(ready_id, _)| *ready_id != id);
+                    if pending_evals.remove(&id).is_some() || ready_scripts.len() != queued {
+                        res(Response::Err {
+                            id,
+                            message: "Timed out waiting for the page".into(),
+                        });
+                    }
+                }
+
+                // Drain everything that's queued so a burst of requests doesn't wait a frame each.
+                // A batch's requests are handled before anything else.
+                loop {
+                    let req = match batch
+                        .as_mut()
+                        .and_then(|(_, requests)| requests.pop_front())
+                    {
+                        Some(req) => req,
+                        None => {
+                            if let Some((id, _)) = batch.take() {
+                                res(Response::Result {
+                                    id,
+                                    result: ResultType::Batch(batch_responses.take()),
+                                });
+                            }
+                            match rx.try_recv() {
+                                Ok(req) => req,
+                                Err(_) => break,
+                            }
+                        }
+                    };
+                    debug!(request = ?req, "Processing request");
+                    let in_batch = batch.is_some();
+                    if trace_rpc && !in_batch {
+                        info!(id = req.id(), request = ?req, "Received request");
+                        request_times.lock().insert(req.id(), Instant::now());
+                    }
+                    let res = |response: Response| {
+                        if in_batch {
+                            batch_responses.borrow_mut().push(response);
+                        } else {
+                            res(response);
+                        }
+                    };
+                    if in_batch && !req.is_batchable() {
+                        res(Response::Err {
+                            id: req.id(),
+                            message: "Request can't be batched".into(),
+                        });
+                        continue;
+                    }
+                    let id = req.id();
+                    // A panic in a handler, e.g. from a platform call that failed, fails its
+                    // request rather than the whole process. Whatever state the handler was
+                    // changing is left as it was when it panicked, which at worst leaves a request
+                    // without its response. Panics inside GTK callbacks still abort.
+                    let handled = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        match req {
+                            Request::Batch { id, requests } => {
+                                batch = Some((id, requests.into()));
+                            }
+                            Request::Eval {
+                                id,
+                                js,
+                                frame,
+                                binary,
+                            } if frame.is_some() || binary => {
+                                let script = match &frame {
+                                    Some(frame) => frame_eval_script(frame, &js, binary),
+                                    // Indirect eval runs in the global scope like an ordinary eval.
+                                    None => binary_eval_script("(0, eval)", &js),
+                                };
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    &script,
+                                    move |result| match result {
+                                        Ok(data) if binary => Response::Result {
+                                            id,
+                                            result: data.as_str().unwrap_or_default().to_string().into(),
+                                        },
+                                        Ok(_) => Response::Ack { id },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::Eval { id, js, .. } => {
+                                let result = webview.evaluate_script(&js);
+                                res(match result {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => {
+                                        error!("Eval error: {:?}", err);
+                                        Response::Err {
+                                            id,
+                                            message: err.to_string(),
+                                        }
+                                    }
+                                });
+                            }
+                            Request::SetTitle { id, title } => {
+                                window.set_title(title.as_str());
+                                if webview_options.lock_title {
+                                    if let Err(err) =
+                                        webview.evaluate_script(&document_title_script(&title))
+                                    {
+                                        error!("Failed to set page title: {:?}", err);
+                                    }
+                                }
+                                res(Response::Ack { id });
+                            }
+                            Request::GetTitle {
+                                id,
+                                source: TitleSource::Window,
+                            } => res(Response::Result {
+                                id,
+                                result: window.title().into(),
+                            }),
+                            Request::GetTitle {
+                                id,
+                                source: TitleSource::Document,
+                            } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    "return document.title;",
+                                    move |result| match result {
+                                        Ok(title) => Response::Result {
+                                            id,
+                                            result: title
+                                                .as_str()
+                                                .unwrap_or_default()
+                                                .to_string()
+                                                .into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::OpenDevTools { id } => {
+                                #[cfg(feature = "devtools")]
+                                {
+                                    webview.open_devtools();
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(not(feature = "devtools"))]
+                                {
+                                    res(Response::Err {
+                                        id,
+                                        message: "DevTools not enabled".to_string(),
+                                    });
+                                }
+                            }
+                            Request::CloseDevTools { id } => {
+                                #[cfg(feature = "devtools")]
+                                {
+                                    webview.close_devtools();
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(not(feature = "devtools"))]
+                                {
+                                    res(Response::Err {
+                                        id,
+                                        message: "DevTools not enabled".to_string(),
+                                    });
+                                }
+                            }
+                            Request::IsDevToolsOpen { id } => {
+                                #[cfg(feature = "devtools")]
+                                {
+                                    res(Response::Result {
+                                        id,
+                                        result: webview.is_devtools_open().into(),
+                                    });
+                                }
+                                #[cfg(not(feature = "devtools"))]
+                                {
+                                    res(Response::Err {
+                                        id,
+                                        message: "DevTools not enabled".to_string(),
+                                    });
+                                }
+                            }
+                            Request::SetVisibility { id, visible } => {
+                                window.set_visible(visible);
+                                window.request_redraw();
+                                res(Response::Ack { id });
+                            }
+                            Request::SetScrollBehavior {
+                                id,
+                                smooth_scrolling,
+                                overscroll,
+                            } => {
+                                if let Some(smooth_scrolling) = smooth_scrolling {
+                                    scroll_behavior.smooth_scrolling = Some(smooth_scrolling);
+                                    set_smooth_scrolling(&webview, smooth_scrolling);
+                                }
+                                if let Some(overscroll) = overscroll {
+                                    scroll_behavior.overscroll = Some(overscroll);
+                                }
+                                let script = scroll_behavior.script();
+                                #[cfg(target_os = "linux")]
+                                {
+                                    if let Some(user_script) = scroll_user_script.take() {
+                                        remove_user_script(&webview, &user_script);
+                                    }
+                                    scroll_user_script = Some(add_user_script(&webview, &script));
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    scroll_behavior_set = true;
+                                }
+                                res(match webview.evaluate_script(&script) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::SetFollowSystemTheme { id, follow, theme } => {
+                                follow_system_theme = follow;
+                                if follow {
+                                    window.set_theme(None);
+                                } else {
+                                    let theme = theme.unwrap_or(Theme::from_tao(window.theme()));
+                                    window.set_theme(Some(theme.to_tao()));
+                                }
+                                res(Response::Ack { id });
+                            }
+                            Request::SetScrollbarsVisible { id, visible } => {
+                                scroll_behavior.scrollbars_hidden = !visible;
+                                let script = scroll_behavior.script();
+                                #[cfg(target_os = "linux")]
+                                {
+                                    if let Some(user_script) = scroll_user_script.take() {
+                                        remove_user_script(&webview, &user_script);
+                                    }
+                                    scroll_user_script = Some(add_user_script(&webview, &script));
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    scroll_behavior_set = true;
+                                }
+                                res(match webview.evaluate_script(&script) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::RespondToPage {
+                                id,
+                                rpc_id,
+                                data,
+                                error,
+                            } => {
+                                if !webview_options.ipc {
+                                    res(Response::Err {
+                                        id,
+                                        message: "The page can't send requests unless `ipc` is set"
+                                            .into(),
+                                    });
+                                    return false;
+                                }
+                                let script = respond_to_page_script(rpc_id, &data, error.as_deref());
+                                res(match webview.evaluate_script(&script) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::SetDragRegionHeight { id, height } => {
+                                if height < 0.0 {
+                                    res(Response::Err {
+                                        id,
+                                        message: "The drag region height can't be negative".into(),
+                                    });
+                                    return false;
+                                }
+                                drag_region_height = height;
+                                let script = drag_region_script(height);
+                                #[cfg(target_os = "linux")]
+                                {
+                                    if let Some(user_script) = drag_region_user_script.take() {
+                                        remove_user_script(&webview, &user_script);
+                                    }
+                                    drag_region_user_script = Some(add_user_script(&webview, &script));
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    drag_region_set = true;
+                                }
+                                res(match webview.evaluate_script(&script) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::SetTouchEmulation { id, enabled } => {
+                                touch_emulation = enabled;
+                                let script = touch_emulation_script(enabled);
+                                #[cfg(target_os = "linux")]
+                                {
+                                    if let Some(user_script) = touch_emulation_user_script.take() {
+                                        remove_user_script(&webview, &user_script);
+                                    }
+                                    touch_emulation_user_script =
+                                        Some(add_user_script(&webview, &script));
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    touch_emulation_set = true;
+                                }
+                                res(match webview.evaluate_script(&script) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::GetDragRegionHeight { id } => res(Response::Result {
+                                id,
+                                result: ResultType::Float(drag_region_height),
+                            }),
+                            Request::SetTextZoom { id, factor } => {
+                                if factor <= 0.0 {
+                                    res(Response::Err {
+                                        id,
+                                        message: "The text zoom factor must be greater than 0".into(),
+                                    });
+                                    return false;
+                                }
+                                res(match set_text_zoom(&webview, factor) {
+                                    Ok(()) => Response::Ack { id },
+                                    Err(message) => Response::Err { id, message },
+                                });
+                            }
+                            Request::SetTrafficLights { id, inset, visible } => {
+                                #[cfg(target_os = "macos")]
+                                {
+                                    use tao::platform::macos::WindowExtMacOS;
+                                    if let Some(inset) = inset {
+                                        window.set_traffic_light_inset(dpi::LogicalPosition::new(
+                                            inset.x, inset.y,
+                                        ));
+                                    }
+                                    if let Some(visible) = visible {
+                                        set_traffic_lights_visible(&window, visible);
+                                    }
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(not(target_os = "macos"))]
+                                {
+                                    let _ = (inset, visible);
+                                    res(Response::Err {
+                                        id,
+                                        message: "Traffic lights are only supported on macOS".into(),
+                                    });
+                                }
+                            }
+                            Request::MoveToMonitor {
+                                id,
+                                monitor,
+                                maximize_to_work_area,
+                            } => {
+                                let Some(handle) = window
+                                    .available_monitors()
+                                    .find(|handle| handle.name().as_deref() == Some(monitor.as_str()))
+                                else {
+                                    res(Response::Err {
+                                        id,
+                                        message: format!("No monitor named {}", monitor),
+                                    });
+                                    return false;
+                                };
+                                let area = monitor_work_area(&handle);
+                                let scale_factor = window.scale_factor();
+                                let outer = window.outer_size().to_logical::<f64>(scale_factor);
+                                let (width, height) = if maximize_to_work_area {
+                                    // The inner size leaves room for the title bar and borders.
+                                    let inner = window.inner_size().to_logical::<f64>(scale_factor);
+                                    window.set_maximized(false);
+                                    window.set_inner_size(dpi::LogicalSize::new(
+                                        area.width - (outer.width - inner.width),
+                                        area.height - (outer.height - inner.height),
+                                    ));
+                                    (area.width, area.height)
+                                } else {
+                                    (outer.width, outer.height)
+                                };
+                                window.set_outer_position(dpi::LogicalPosition::new(
+                                    area.x + ((area.width - width) / 2.0).max(0.0),
+                                    area.y + ((area.height - height) / 2.0).max(0.0),
+                                ));
+                                res(Response::Ack { id });
+                            }
+                            Request::RequestRedraw { id } => {
+                                window.request_redraw();
+                                res(Response::Ack { id });
+                            }
+                            Request::SetAlwaysOnBottom {
+                                id,
+                                always_on_bottom,
+                            } => {
+                                window.set_always_on_bottom(always_on_bottom);
+                                res(Response::Ack { id });
+                            }
+                            Request::SetCursorPosition { id, x, y } => {
+                                #[cfg(target_os = "linux")]
+                                if wayland {
+                                    res(Response::Err {
+                                        id,
+                                        message: "Wayland doesn't let applications move the pointer"
+                                            .into(),
+                                    });
+                                    return false;
+                                }
+                                res(
+                                    match window.set_cursor_position(dpi::LogicalPosition::new(x, y)) {
+                                        Ok(_) => Response::Ack { id },
+                                        Err(err) => Response::Err {
+                                            id,
+                                            message: err.to_string(),
+                                        },
+                                    },
+                                );
+                            }
+                            Request::SetIgnoreCursorEventsRegion { id, interactive } => {
+                                interactive_regions = interactive;
+                                if interactive_regions.is_empty() {
+                                    cursor_poll_at = None;
+                                    cursor_ignored = false;
+                                    res(match window.set_ignore_cursor_events(false) {
+                                        Ok(_) => Response::Ack { id },
+                                        Err(err) => Response::Err {
+                                            id,
+                                            message: err.to_string(),
+                                        },
+                                    });
+                                } else {
+                                    cursor_poll_at = Some(Instant::now());
+                                    res(Response::Ack { id });
+                                }
+                            }
+                            Request::IsVisible { id } => res(Response::Result {
+                                id,
+                                result: window.is_visible().into(),
+                            }),
+                            Request::SetWebviewVisible { id, visible } => {
+                                res(match webview.set_visible(visible) {
+                                    Ok(_) => {
+                                        webview_visible = visible;
+                                        Response::Ack { id }
+                                    }
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::IsWebviewVisible { id } => res(Response::Result {
+                                id,
+                                result: webview_visible.into(),
+                            }),
+                            Request::Raise { id } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use gtk::prelude::WidgetExt;
+                                    use tao::platform::unix::WindowExtUnix;
+
+                                    if let Some(gdk_window) = window.gtk_window().window() {
+                                        gdk_window.raise();
+                                    }
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(target_os = "windows")]
+                                {
+                                    use tao::platform::windows::WindowExtWindows;
+                                    use windows::Win32::Foundation::HWND;
+                                    use windows::Win32::UI::WindowsAndMessaging::{
+                                        SetWindowPos, HWND_TOP, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE,
+                                    };
+
+                                    // SAFETY: The handle belongs to the window, which outlives the call.
+                                    let result = unsafe {
+                                        SetWindowPos(
+                                            HWND(window.hwnd() as _),
+                                            Some(HWND_TOP),
+                                            0,
+                                            0,
+                                            0,
+                                            0,
+                                            SWP_NOACTIVATE | SWP_NOMOVE | SWP_NOSIZE,
+                                        )
+                                    };
+                                    res(match result {
+                                        Ok(_) => Response::Ack { id },
+                                        Err(err) => Response::Err {
+                                            id,
+                                            message: err.to_string(),
+                                        },
+                                    });
+                                }
+                                // tao can't bring a window to the front without focusing it on macOS.
+                                #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+                                {
+                                    window.set_focus();
+                                    res(Response::Ack { id });
+                                }
+                            }
+                            Request::FocusWebview { id } => {
+                                res(match webview.focus() {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::IsWebviewFocused { id } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use gtk::prelude::WidgetExt;
+                                    use wry::WebViewExtUnix;
+
+                                    res(Response::Result {
+                                        id,
+                                        result: (window.is_focused() && webview.webview().has_focus())
+                                            .into(),
+                                    });
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                res(Response::Err {
+                                    id,
+                                    message: "Querying webview focus is only supported on Linux".into(),
+                                });
+                            }
+                            Request::GetVersion { id } => {
+                                res(Response::Result {
+                                    id,
+                                    result: VERSION.to_string().into(),
+                                });
+                            }
+                            Request::CaptureElement { id, selector } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use webkit2gtk::{SnapshotOptions, SnapshotRegion, WebViewExt};
+                                    use wry::WebViewExtUnix;
+
+                                    let view = webview.webview();
+                                    let capture_tx = tx.clone();
+                                    if let Some(response) = eval_correlated_deferred(
+                                        &webview,
+                                        &mut pending_evals,
+                                        id,
+                                        &element_rect_script(&selector),
+                                        move |result| {
+                                            let rect = match result.and_then(|value| {
+                                                serde_json::from_value::<ElementRect>(value)
+                                                    .map_err(|err| err.to_string())
+                                            }) {
+                                                Ok(rect) => rect,
+                                                Err(message) => {
+                                                    return Some(Response::Err { id, message })
+                                                }
+                                            };
+                                            view.snapshot(
+                                                SnapshotRegion::FullDocument,
+                                                SnapshotOptions::NONE,
+                                                None::<&webkit2gtk::gio::Cancellable>,
+                                                move |snapshot| {
+                                                    let response = match snapshot
+                                                        .map_err(|err| err.to_string())
+                                                        .and_then(|snapshot| {
+                                                            crop_to_png(&snapshot, &rect)
+                                                        }) {
+                                                        Ok(png) => Response::Result {
+                                                            id,
+                                                            result: BASE64.encode(png).into(),
+                                                        },
+                                                        Err(message) => Response::Err { id, message },
+                                                    };
+                                                    let _ =
+                                                        capture_tx.send(Message::Response(response));
+                                                },
+                                            );
+                                            None
+                                        },
+                                    ) {
+                                        res(response);
+                                    }
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    let _ = selector;
+                                    res(Response::Err {
+                                        id,
+                                        message: "Capturing elements is only supported on Linux".into(),
+                                    });
+                                }
+                            }
+                            Request::Echo { id, payload } => {
+                                res(Response::Result {
+                                    id,
+                                    result: payload.into(),
+                                });
+                            }
+                            Request::Hello {
+                                id,
+                                client_protocol_version,
+                            } => {
+                                let supported = (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION)
+                                    .contains(&client_protocol_version);
+                                if !supported {
+                                    warn!(
+                                        client_protocol_version,
+                                        "Client speaks an unsupported protocol version"
+                                    );
+                                }
+                                res(Response::Result {
+                                    id,
+                                    result: serde_json::json!({
+                                        "min": MIN_PROTOCOL_VERSION,
+                                        "max": PROTOCOL_VERSION,
+                                        "supported": supported,
+                                    })
+                                    .into(),
+                                });
+                            }
+                            Request::RecreateWebview { id } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use webkit2gtk::WebViewExt;
+                                    use wry::WebViewExtUnix;
+
+                                    info!("Recreating the web content process");
+                                    webview.webview().terminate_web_process();
+                                    for (eval_id, respond) in pending_evals.drain() {
+                                        let message = "The webview was recreated".to_string();
+                                        match respond(Err(message)) {
+                                            Some(response) => res(response),
+                                            None => debug!(eval_id, "Dropped deferred response"),
+                                        }
+                                    }
+                                    res(match webview.reload() {
+                                        Ok(_) => {
+                                            page_loading = true;
+                                            notify(Notification::WebviewRecreated);
+                                            Response::Ack { id }
+                                        }
+                                        Err(err) => {
+                                            error!("Failed to reload after recreating: {:?}", err);
+                                            Response::Err {
+                                                id,
+                                                message: err.to_string(),
+                                            }
+                                        }
+                                    });
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                res(Response::Err {
+                                    id,
+                                    message: "Recreating the webview is only supported on Linux".into(),
+                                });
+                            }
+                            Request::Ping { id } => {
+                                res(Response::Result {
+                                    id,
+                                    result: "pong".to_string().into(),
+                                });
+                            }
+                            Request::DumpWindowState { id } => res(Response::Result {
+                                id,
+                                result: window_state(&window, &webview, webview_visible, page_loading)
+                                    .into(),
+                            }),
+                            Request::GetSize {
+                                id,
+                                include_decorations,
+                            } => {
+                                let size: dpi::LogicalSize<f64> =
+                                    if include_decorations.unwrap_or(false) {
+                                        window.outer_size().to_logical(window.scale_factor())
+                                    } else {
+                                        window.inner_size().to_logical(window.scale_factor())
+                                    };
+                                let (width, height) =
+                                    unmapped_size((size.width, size.height), unmapped_inner_size);
+                                res(Response::Result {
+                                    id,
+                                    result: ResultType::Size(SizeWithScale {
+                                        width,
+                                        height,
+                                        scale_factor: window.scale_factor(),
+                                    }),
+                                });
+                            }
+                            Request::SetSize { id, size } => {
+                                if unmapped_inner_size.is_some() {
+                                    unmapped_inner_size = Some((size.width, size.height));
+                                }
+                                window.set_inner_size(dpi::Size::Logical(dpi::LogicalSize::new(
+                                    size.width,
+                                    size.height,
+                                )));
+                                window.request_redraw();
+                                res(Response::Ack { id });
+                            }
+                            Request::SetResizeConstraints {
+                                id,
+                                aspect_ratio: new_aspect_ratio,
+                                resize_increments: new_resize_increments,
+                            } => {
+                                aspect_ratio = new_aspect_ratio;
+                                resize_increments =
+                                    new_resize_increments.map(|size| (size.width, size.height));
+                                let size = window.inner_size().to_logical::<f64>(window.scale_factor());
+                                let (width, height) = constrain_size(
+                                    (size.width, size.height),
+                                    resize_increments,
+                                    aspect_ratio,
+                                );
+                                window.set_inner_size(dpi::LogicalSize::new(width, height));
+                                res(Response::Ack { id });
+                            }
+                            Request::Fullscreen { id, fullscreen } => {
+                                let fullscreen = fullscreen.unwrap_or(window.fullscreen().is_none());
+                                eprintln!("Fullscreen: {:?}", fullscreen);
+                                if fullscreen {
+                                    window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                                } else {
+                                    window.set_fullscreen(None);
+                                }
+                                res(Response::Ack { id });
+                            }
+                            Request::Maximize { id, maximized } => {
+                                let maximized = maximized.unwrap_or(!window.is_maximized());
+                                eprintln!("Maximize: {:?}", maximized);
+                                window.set_maximized(maximized);
+                                res(Response::Ack { id });
+                            }
+                            Request::Minimize { id, minimized } => {
+                                let minimized = minimized.unwrap_or(!window.is_minimized());
+                                eprintln!("Minimize: {:?}", minimized);
+                                window.set_minimized(minimized);
+                                res(Response::Ack { id });
+                            }
+                            Request::IsFullscreen { id } => {
+                                res(Response::Result {
+                                    id,
+                                    result: window.fullscreen().is_some().into(),
+                                });
+                            }
+                            Request::IsMaximized { id } => {
+                                res(Response::Result {
+                                    id,
+                                    result: window.is_maximized().into(),
+                                });
+                            }
+                            Request::IsMinimized { id } => {
+                                res(Response::Result {
+                                    id,
+                                    result: window.is_minimized().into(),
+                                });
+                            }
+                            Request::LoadHtml {
+                                id,
+                                html,
+                                origin,
+                                charset,
+                                replace,
+                            } => {
+                                let encoding = match html_encoding(&charset) {
+                                    Ok(encoding) => encoding,
+                                    Err(message) => {
+                                        res(Response::Err { id, message });
+                                        return false;
+                                    }
+                                };
+                                *html_mutex.lock() = html;
+                                *charset_mutex.lock() = encoding;
+                                *directory_mutex.lock() = None;
+                                let origin = match origin {
+                                    Some(origin) => {
+                                        origin_mutex.lock().clone_from(&origin);
+                                        origin
+                                    }
+                                    None => origin_mutex.lock().clone(),
+                                };
+
+                                let url = format!("load-html://{}?{}", origin, id);
+                                let loaded = if replace {
+                                    webview.evaluate_script(&replace_location_script(&url))
+                                } else {
+                                    webview.load_url(&url)
+                                };
+                                match loaded {
+                                    Ok(_) => {
+                                        page_loading = true;
+                                        res(Response::Ack { id })
+                                    }
+                                    Err(err) => res(Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    }),
+                                }
+                            }
+                            Request::LoadUrl {
+                                id,
+                                url,
+                                headers,
+                                referer,
+                                replace: true,
+                            } => {
+                                if headers.is_some() || referer.is_some() {
+                                    res(Response::Err {
+                                        id,
+                                        message: "Headers can't be sent when replacing the page".into(),
+                                    });
+                                    return false;
+                                }
+                                // Let the navigation through even though it's without the default headers.
+                                if !webview_options.default_headers.is_empty() {
+                                    *navigated_with_headers.lock() = Some(url.clone());
+                                }
+                                match webview.evaluate_script(&replace_location_script(&url)) {
+                                    Ok(_) => {
+                                        page_loading = true;
+                                        res(Response::Ack { id })
+                                    }
+                                    Err(err) => res(Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    }),
+                                }
+                            }
+                            Request::LoadUrl {
+                                id,
+                                url,
+                                headers,
+                                referer,
+                                replace: false,
+                            } => {
+                                if !webview_options.default_headers.is_empty() {
+                                    *navigated_with_headers.lock() = Some(url.clone());
+                                }
+                                let headers = match referer {
+                                    Some(referer) => Some(with_referer(
+                                        headers.unwrap_or_default(),
+                                        Some(&referer),
+                                    )),
+                                    None => headers,
+                                };
+                                let resp = match with_default_headers(
+                                    &webview_options.default_headers,
+                                    headers,
+                                ) {
+                                    Some(headers) => {
+                                        webview.load_url_with_headers(&url, parse_headers(headers))
+                                    }
+                                    None => webview.load_url(&url),
+                                };
+                                match resp {
+                                    Ok(_) => {
+                                        page_loading = true;
+                                        res(Response::Ack { id })
+                                    }
+                                    Err(err) => res(Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    }),
+                                }
+                            }
+                            Request::WhenReady { id, js, timeout_ms } => {
+                                ready_deadlines
+                                    .push((Instant::now() + Duration::from_millis(timeout_ms), id));
+                                if page_loading {
+                                    ready_scripts.push((id, js));
+                                } else if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    &when_ready_script(&js),
+                                    move |result| json_result(id, result),
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::AddScript { id, script } => {
+                                if let Err(err) = webview.evaluate_script(&script) {
+                                    error!("Failed to run added script: {:?}", err);
+                                }
+                                #[cfg(target_os = "linux")]
+                                added_scripts.insert(id, add_user_script(&webview, &script));
+                                #[cfg(not(target_os = "linux"))]
+                                added_scripts.push((id, script));
+                                res(Response::Ack { id });
+                            }
+                            Request::FindInPage { id, query, .. } if query.is_empty() => {
+                                stop_find(&webview, &find_state);
+                                notify(Notification::FindResult {
+                                    matches: 0,
+                                    active: 0,
+                                });
+                                res(Response::Ack { id });
+                            }
+                            Request::FindInPage {
+                                id,
+                                query,
+                                forward,
+                                match_case,
+                            } => {
+                                let step = find_state.borrow_mut().search(&query, forward, match_case);
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use webkit2gtk::{FindControllerExt, FindOptions, WebViewExt};
+                                    use wry::WebViewExtUnix;
+
+                                    let Some(find_controller) = webview.webview().find_controller()
+                                    else {
+                                        res(Response::Err {
+                                            id,
+                                            message: "The webview has no find controller".to_string(),
+                                        });
+                                        return false;
+                                    };
+                                    match step {
+                                        FindStep::Start { forward } => {
+                                            let mut options = FindOptions::WRAP_AROUND;
+                                            if !match_case {
+                                                options |= FindOptions::CASE_INSENSITIVE;
+                                            }
+                                            if !forward {
+                                                options |= FindOptions::BACKWARDS;
+                                            }
+                                            find_controller.search(
+                                                &query,
+                                                options.bits(),
+                                                FIND_MAX_MATCHES,
+                                            );
+                                        }
+                                        FindStep::Next => find_controller.search_next(),
+                                        FindStep::Previous => find_controller.search_previous(),
+                                    }
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    let find_state = find_state.clone();
+                                    let find_tx = tx.clone();
+                                    if let Some(response) = eval_correlated(
+                                        &webview,
+                                        &mut pending_evals,
+                                        id,
+                                        &find_script(&query, step, match_case),
+                                        move |result| match result {
+                                            Ok(matches) => {
+                                                let matches = matches.as_u64().unwrap_or(0) as u32;
+                                                let active = find_state.borrow_mut().found(matches);
+                                                let _ = find_tx.send(Message::Notification(
+                                                    Notification::FindResult { matches, active },
+                                                ));
+                                                Response::Ack { id }
+                                            }
+                                            Err(message) => Response::Err { id, message },
+                                        },
+                                    ) {
+                                        res(response);
+                                    }
+                                }
+                            }
+                            Request::StopFind { id } => {
+                                stop_find(&webview, &find_state);
+                                res(Response::Ack { id });
+                            }
+                            Request::ClipboardAction { id, action } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    &format!("return document.execCommand({:?});", action.command()),
+                                    move |result| match result {
+                                        Ok(ran) => Response::Result {
+                                            id,
+                                            result: ran.as_bool().unwrap_or(false).into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::ReadClipboard { id } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    let clipboard_tx = tx.clone();
+                                    gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD).request_text(
+                                        move |_, text| {
+                                            let _ = clipboard_tx.send(Message::Response(
+                                                Response::Result {
+                                                    id,
+                                                    result: text
+                                                        .map(|text| text.to_string())
+                                                        .unwrap_or_default()
+                                                        .into(),
+                                                },
+                                            ));
+                                        },
+                                    );
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                res(Response::Err {
+                                    id,
+                                    message: "Reading the clipboard is only supported on Linux".into(),
+                                });
+                            }
+                            Request::WriteClipboard { id, text } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    let clipboard =
+                                        gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
+                                    clipboard.set_text(&text);
+                                    // Keeps the text on the clipboard after the webview exits.
+                                    clipboard.store();
+                                    res(Response::Ack { id });
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    let _ = text;
+                                    res(Response::Err {
+                                        id,
+                                        message: "Writing the clipboard is only supported on Linux"
+                                            .into(),
+                                    });
+                                }
+                            }
+                            Request::GetSelection { id } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    GET_SELECTION_SCRIPT,
+                                    move |result| match result {
+                                        Ok(text) => Response::Result {
+                                            id,
+                                            result: text.as_str().unwrap_or_default().to_string().into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::ClearSelection { id } => {
+                                res(match webview.evaluate_script(CLEAR_SELECTION_SCRIPT) {
+                                    Ok(_) => Response::Ack { id },
+                                    Err(err) => Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    },
+                                });
+                            }
+                            Request::Click { id, x, y } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    &click_script(x, y),
+                                    move |result| match result {
+                                        Ok(hit) => Response::Result {
+                                            id,
+                                            result: hit.as_bool().unwrap_or(false).into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::GetFrames { id } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    GET_FRAMES_SCRIPT,
+                                    move |result| match result {
+                                        Ok(frames) => Response::Result {
+                                            id,
+                                            result: frames.into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::GetPerformanceTiming { id } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    PERFORMANCE_TIMING_SCRIPT,
+                                    move |result| match result {
+                                        Ok(timing) => Response::Result {
+                                            id,
+                                            result: timing.into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::GetLastNavigationChain { id } => {
+                                let urls = navigation_chain.lock().urls.clone();
+                                res(Response::Result {
+                                    id,
+                                    result: serde_json::json!(urls).into(),
+                                });
+                            }
+                            Request::ExportStorage { id } => {
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    EXPORT_STORAGE_SCRIPT,
+                                    move |result| match result {
+                                        Ok(storage) => Response::Result {
+                                            id,
+                                            result: storage.into(),
+                                        },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::ImportStorage { id, data } => {
+                                let body = import_storage_script(id, &data);
+                                // Errors writing to later pages, e.g. when over quota, can only be
+                                // logged to their console.
+                                let script = format!(
+                                    "(() => {{ try {{ (() => {{\n{}\n}})(); }} catch (error) {{ console.error(error); }} }})();",
+                                    body
+                                );
+                                #[cfg(target_os = "linux")]
+                                added_scripts.insert(id, add_user_script(&webview, &script));
+                                #[cfg(not(target_os = "linux"))]
+                                added_scripts.push((id, script));
+                                if let Some(response) = eval_correlated(
+                                    &webview,
+                                    &mut pending_evals,
+                                    id,
+                                    &body,
+                                    move |result| match result {
+                                        Ok(_) => Response::Ack { id },
+                                        Err(message) => Response::Err { id, message },
+                                    },
+                                ) {
+                                    res(response);
+                                }
+                            }
+                            Request::RemoveScript { id, script_id } => {
+                                #[cfg(target_os = "linux")]
+                                let removed = added_scripts
+                                    .remove(&script_id)
+                                    .map(|user_script| remove_user_script(&webview, &user_script));
+                                #[cfg(not(target_os = "linux"))]
+                                let removed = added_scripts
+                                    .iter()
+                                    .position(|(added_id, _)| *added_id == script_id)
+                                    .map(|index| {
+                                        added_scripts.remove(index);
+                                    });
+                                res(match removed {
+                                    Some(()) => Response::Ack { id },
+                                    None => Response::Err {
+                                        id,
+                                        message: format!("No script was added with id {}", script_id),
+                                    },
+                                });
+                            }
+                            Request::Close { id } => {
+                                info!("Webview close requested by client");
+                                res(Response::Ack { id });
+                                notify(Notification::Closed {
+                                    reason: CloseReason::ClientRequested,
+                                });
+                                *control_flow = ControlFlow::Exit;
+                                return true;
+                            }
+                            Request::ProtocolResponse {
+                                id,
+                                request_id,
+                                status,
+                                headers,
+                                body,
+                            } => match protocol_response(status, headers, &body) {
+                                Ok(response) => {
+                                    match pending_protocol_requests.lock().remove(&request_id) {
+                                        Some(responder) => {
+                                            responder.respond(response);
+                                            res(Response::Ack { id });
+                                        }
+                                        None => res(Response::Err {
+                                            id,
+                                            message: format!(
+                                                "No pending protocol request with id {}",
+                                                request_id
+                                            ),
+                                        }),
+                                    }
+                                }
+                                Err(message) => res(Response::Err { id, message }),
+                            },
+                            Request::PermissionDecision {
+                                id,
+                                request_id,
+                                allow,
+                            } => {
+                                #[cfg(target_os = "linux")]
+                                {
+                                    use webkit2gtk::PermissionRequestExt;
+                                    match pending_permissions.borrow_mut().remove(&request_id) {
+                                        Some(request) => {
+                                            if allow {
+                                                request.allow();
+                                            } else {
+                                                request.deny();
+                                            }
+                                            res(Response::Ack { id });
+                                        }
+                                        None => res(Response::Err {
+                                            id,
+                                            message: format!(
+                                                "No pending permission request with id {}",
+                                                request_id
+                                            ),
+                                        }),
+                                    }
+                                }
+                                #[cfg(not(target_os = "linux"))]
+                                {
+                                    let _ = (request_id, allow);
+                                    res(Response::Err {
+                                        id,
+                                        message:
+                                            "Permission requests are not supported on this platform"
+                                                .to_string(),
+                                    });
+                                }
+                            }
+                            Request::LoadDirectory { id, path, entry } => {
+                                *directory_mutex.lock() = Some(PathBuf::from(path));
+                                let origin = origin_mutex.lock().clone();
+                                match webview.load_url(&format!(
+                                    "load-html://{}/{}?{}",
+                                    origin,
+                                    entry.trim_start_matches('/'),
+                                    id
+                                )) {
+                                    Ok(_) => {
+                                        page_loading = true;
+                                        res(Response::Ack { id })
+                                    }
+                                    Err(err) => res(Response::Err {
+                                        id,
+                                        message: err.to_string(),
+                                    }),
+                                }
+                            }
+                        }
+                        false
+                    }));
+                    match handled {
+                        Ok(false) => (),
+                        // The webview is closing.
+                        Ok(true) => break,
+                        Err(panic) => {
+                            let message = panic_message(panic.as_ref());
+                            error!(id, message, "Request handler panicked");
+                            res(Response::Err {
+                                id,
+                                message: format!("Handling the request panicked: {}", message),
+                            });
+                        }
+                    }
+                }
+
+                if cursor_poll_at.is_some_and(|at| now >= at) {
+                    cursor_poll_at = Some(now + CURSOR_POLL_INTERVAL);
+                    if let Some(inside) = cursor_in_regions(&window, &interactive_regions) {
+                        if inside == cursor_ignored {
+                            cursor_ignored = !inside;
+                            if let Err(err) = window.set_ignore_cursor_events(cursor_ignored) {
+                                error!(
+                                    "Failed to set whether cursor events are ignored: {:?}",
+                                    err
+                                );
+                            }
+                        }
+                    }
                 }
-                JsonEvent::ValueInt => {
-                    json_string.push_str(&parser.current_int::<i64>().unwrap().to_string());
+
+                // Checked once everything's handled, since hiding or minimizing the window can
+                // happen through a request as well as from the user.
+                if webview_options.pause_animations_when_hidden {
+                    let hidden = !window.is_visible() || window.is_minimized();
+                    if hidden != animations_paused {
+                        animations_paused = hidden;
+                        debug!(
+                            paused = hidden,
+                            "Window visibility changed, toggling animations"
+                        );
+                        if let Err(err) = webview.evaluate_script(&animations_paused_script(hidden))
+                        {
+                            error!("Failed to toggle animations: {:?}", err);
+                        }
+                    }
                 }
-                JsonEvent::ValueFloat => {
-                    json_string.push_str(&parser.current_float().unwrap().to_string());
+            }
+            _ => (),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Creates a request queue for tests along with the receiving ends of its channels.
+    fn test_queue(
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> (
+        RequestQueue,
+        mpsc::Receiver<Request>,
+        mpsc::Receiver<Message>,
+    ) {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let (responses, responses_receiver) = mpsc::channel();
+        let queue = RequestQueue {
+            sender,
+            overflow,
+            responses,
+            echo_requests: false,
+        };
+        (queue, receiver, responses_receiver)
+    }
+
+    #[test]
+    fn test_process_input_simple() {
+        // Create a GetVersion request
+        let request = Request::GetVersion { id: 0 };
+
+        // Serialize to JSON
+        let json = serde_json::to_vec(&request).unwrap();
+        let cursor = Cursor::new(json);
+        let reader = BufReader::new(cursor);
+        let (queue, receiver, _) = test_queue(16, OverflowPolicy::Block);
+
+        // Capture stderr output
+        let stderr = std::io::stderr();
+        let _handle = stderr.lock();
+
+        process_input(reader, queue);
+
+        // Give the thread a moment to process
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Try to receive the message
+        match receiver.try_recv() {
+            Ok(received) => {
+                assert!(matches!(
+                    received,
+                    Request::GetVersion { id } if id == 0
+                ));
+            }
+            Err(e) => panic!("Failed to receive message: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_process_input_complex() {
+        // Create a SetSize request with nested SimpleSize
+        let request = Request::SetSize {
+            id: 0,
+            size: Size {
+                width: 800.0,
+                height: 600.0,
+            },
+        };
+
+        // Serialize to JSON
+        let json = serde_json::to_vec(&request).unwrap();
+        let cursor = Cursor::new(json);
+        let reader = BufReader::new(cursor);
+        let (queue, receiver, _) = test_queue(16, OverflowPolicy::Block);
+
+        process_input(reader, queue);
+
+        // Give the thread a moment to process
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Try to receive the message
+        match receiver.try_recv() {
+            Ok(received) => match received {
+                Request::SetSize { id, size } => {
+                    assert_eq!(id, 0);
+                    assert_eq!(size.width, 800.0);
+                    assert_eq!(size.height, 600.0);
                 }
-                JsonEvent::ValueTrue => json_string.push_str("true"),
-                JsonEvent::ValueFalse => json_string.push_str("false"),
-                JsonEvent::ValueNull => json_string.push_str("null"),
+                other => panic!("Unexpected request type: {:?}", other),
+            },
+            Err(e) => panic!("Failed to receive message: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_process_output() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let output_clone = output.clone();
+        let (sender, receiver) = mpsc::channel();
+
+        // Start processing output
+        process_output(WriteGuard(output_clone), receiver, None);
+
+        // Create and send a test message
+        let message = Message::Response(Response::Ack { id: 0 });
+        sender.send(message).unwrap();
+
+        // Give the thread a moment to process
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Check the output
+        let output_str = String::from_utf8(output.lock().clone()).unwrap();
+        let expected = serde_json::json!({
+            "$type": "response",
+            "data": {
+                "$type": "ack",
+                "id": 0
             }
-        }
-    });
-}
+        });
+        let expected_str = expected.to_string() + "\n";
+        assert_eq!(output_str, expected_str);
+    }
 
-/// Incrementally writes messages to a writer.
-///
-/// This is used in the main program to write messages to stdout.
-fn process_output<W: Write + std::marker::Send + 'static>(
-    writer: W,
-    receiver: mpsc::Receiver<Message>,
-) {
-    std::thread::spawn(move || {
-        let mut writer = std::io::BufWriter::new(writer);
+    #[test]
+    fn test_process_output_reports_closed_written() {
+        let (sender, receiver) = mpsc::channel();
+        let closed_written = process_output(std::io::sink(), receiver, None);
 
-        while let Ok(event) = receiver.recv() {
-            debug!(message = ?event, "Sending message to client");
-            match serde_json::to_string(&event) {
-                Ok(json) => {
-                    let mut buffer = json.into_bytes();
-                    buffer.push(b'\n');
-                    writer.write_all(&buffer).unwrap();
-                    writer.flush().unwrap();
-                }
-                Err(err) => {
-                    error!("Failed to serialize event: {:?} {:?}", event, err);
-                }
-            }
-        }
-    });
-}
+        sender
+            .send(Message::Response(Response::Ack { id: 0 }))
+            .unwrap();
+        assert!(closed_written
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
 
-pub fn run(webview_options: Options) -> wry::Result<()> {
-    info!("Starting webview with options: {:?}", webview_options);
+        sender
+            .send(Message::Notification(Notification::Closed {
+                reason: CloseReason::Signal,
+            }))
+            .unwrap();
+        assert!(closed_written.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
 
-    // These two mutexes are used to store the html and origin if the webview is created with html.
-    // The html mutex is needed to provide a value to the custom protocol and origin is needed
-    // as a fallback if `load_html` is called without an origin.
-    let html_mutex = Arc::new(Mutex::new("".to_string()));
-    let origin_mutex = Arc::new(Mutex::new(default_origin().to_string()));
+    // Helper struct to implement Write for our Arc<Mutex<Vec<u8>>>
+    struct WriteGuard(Arc<Mutex<Vec<u8>>>);
 
-    let (tx, from_webview) = mpsc::channel::<Message>();
-    let (to_eventloop, rx) = mpsc::channel::<Request>();
+    impl Write for WriteGuard {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().write(buf)
+        }
 
-    let event_loop = EventLoop::new();
-    let mut window_builder = WindowBuilder::new()
-        .with_title(webview_options.title.clone())
-        .with_transparent(webview_options.transparent)
-        .with_decorations(webview_options.decorations);
-    match webview_options.size {
-        Some(WindowSize::States(WindowSizeStates::Maximized)) => {
-            window_builder = window_builder.with_maximized(true)
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().flush()
         }
-        Some(WindowSize::States(WindowSizeStates::Fullscreen)) => {
-            window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)))
+    }
+
+    #[test]
+    fn test_process_input_multiple() {
+        // Create multiple requests
+        let requests = vec![
+            Request::GetVersion { id: 0 },
+            Request::SetSize {
+                id: 0,
+                size: Size {
+                    width: 1024.0,
+                    height: 768.0,
+                },
+            },
+            Request::LoadUrl {
+                id: 0,
+                url: "https://example.com".to_string(),
+                headers: Some(HashMap::from([
+                    ("User-Agent".to_string(), "test-agent".to_string()),
+                    ("Accept".to_string(), "text/html".to_string()),
+                ])),
+                referer: None,
+                replace: false,
+            },
+        ];
+
+        // Serialize each request and concatenate
+        let mut json = Vec::new();
+        for request in &requests {
+            json.extend(serde_json::to_vec(request).unwrap());
         }
-        Some(WindowSize::Size(Size { width, height })) => {
-            window_builder = window_builder
-                .with_inner_size(dpi::Size::Logical(dpi::LogicalSize::new(width, height)))
+
+        let cursor = Cursor::new(json);
+        let reader = BufReader::new(cursor);
+        let (queue, receiver, _) = test_queue(16, OverflowPolicy::Block);
+
+        process_input(reader, queue);
+
+        // Give the thread a moment to process
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Try to receive all messages in order
+        for expected in requests {
+            match receiver.try_recv() {
+                Ok(received) => match (received, expected) {
+                    (Request::GetVersion { id: rid }, Request::GetVersion { id: eid }) => {
+                        assert_eq!(rid, eid);
+                    }
+                    (
+                        Request::SetSize {
+                            id: rid,
+                            size: rsize,
+                        },
+                        Request::SetSize {
+                            id: eid,
+                            size: esize,
+                        },
+                    ) => {
+                        assert_eq!(rid, eid);
+                        assert_eq!(rsize.width, esize.width);
+                        assert_eq!(rsize.height, esize.height);
+                    }
+                    (
+                        Request::LoadUrl {
+                            id: rid,
+                            url: rurl,
+                            headers: rheaders,
+                            ..
+                        },
+                        Request::LoadUrl {
+                            id: eid,
+                            url: eurl,
+                            headers: eheaders,
+                            ..
+                        },
+                    ) => {
+                        assert_eq!(rid, eid);
+                        assert_eq!(rurl, eurl);
+                        assert_eq!(rheaders, eheaders);
+                    }
+                    _ => panic!("Unexpected request type mismatch"),
+                },
+                Err(e) => panic!("Failed to receive message: {:?}", e),
+            }
         }
-        None => (),
+
+        // Verify no more messages
+        assert!(
+            receiver.try_recv().is_err(),
+            "Should not have any more messages"
+        );
     }
-    let window = window_builder.build(&event_loop).unwrap();
 
-    let html_mutex_init = html_mutex.clone();
-    let mut webview_builder = match webview_options.load {
-        Some(Content::Url { url, headers }) => {
-            let mut webview_builder = WebViewBuilder::new().with_url(url);
-            if let Some(headers) = headers {
-                let headers = headers
-                    .into_iter()
-                    .map(|(k, v)| {
+    #[test]
+    fn test_process_output_multiple() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let output_clone = output.clone();
+        let (sender, receiver) = mpsc::channel();
+
+        // Start processing output
+        process_output(WriteGuard(output_clone), receiver, None);
+
+        // Create and send multiple test messages
+        let messages = vec![
+            Message::Response(Response::Ack { id: 0 }),
+            Message::Notification(Notification::Started {
+                version: "1.0.0".to_string(),
+                protocol_version: PROTOCOL_VERSION,
+            }),
+            Message::Response(Response::Result {
+                id: 0,
+                result: ResultType::Size(SizeWithScale {
+                    width: 800.0,
+                    height: 600.0,
+                    scale_factor: 1.0,
+                }),
+            }),
+        ];
+
+        // Send all messages
+        for message in messages.clone() {
+            sender.send(message).unwrap();
+        }
+
+        // Give the thread a moment to process
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Get the output and split by newlines
+        let output_str = String::from_utf8(output.lock().clone()).unwrap();
+        let received_messages: Vec<Message> = output_str
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        // Verify we got all messages in order
+        assert_eq!(received_messages.len(), messages.len());
+        for (received, expected) in received_messages.iter().zip(messages.iter()) {
+            match (received, expected) {
+                (
+                    Message::Response(Response::Ack { id: rid }),
+                    Message::Response(Response::Ack { id: eid }),
+                ) => {
+                    assert_eq!(rid, eid);
+                }
+                (
+                    Message::Notification(Notification::Started { version: rver, .. }),
+                    Message::Notification(Notification::Started { version: ever, .. }),
+                ) => {
+                    assert_eq!(rver, ever);
+                }
+                (
+                    Message::Response(Response::Result {
+                        id: rid,
+                        result: rres,
+                    }),
+                    Message::Response(Response::Result {
+                        id: eid,
+                        result: eres,
+                    }),
+                ) => {
+                    assert_eq!(rid, eid);
+                    match (rres, eres) {
                         (
-                            HeaderName::from_str(&k).unwrap(),
-                            HeaderValue::from_str(&v).unwrap(),
-                        )
-                    })
-                    .collect();
-                webview_builder = webview_builder.with_headers(headers);
+                            ResultType::Size(SizeWithScale {
+                                width: rw,
+                                height: rh,
+                                scale_factor: rs,
+                            }),
+                            ResultType::Size(SizeWithScale {
+                                width: ew,
+                                height: eh,
+                                scale_factor: es,
+                            }),
+                        ) => {
+                            assert_eq!(rw, ew);
+                            assert_eq!(rh, eh);
+                            assert_eq!(rs, es);
+                        }
+                        _ => panic!("Unexpected result type"),
+                    }
+                }
+                _ => panic!("Message type mismatch"),
             }
-            webview_builder
         }
-        Some(Content::Html { html, origin }) => {
-            origin_mutex.lock().clone_from(&origin);
-            *html_mutex.lock() = html;
-            WebViewBuilder::new().with_url(format!("load-html://{}", origin))
+
+        // Verify each line is valid JSON
+        for line in output_str.lines() {
+            assert!(serde_json::from_str::<Message>(line).is_ok());
         }
-        None => WebViewBuilder::new(),
-    }
-    .with_custom_protocol("load-html".into(), move |_id, _req| {
-        HttpResponse::builder()
-            .header("Content-Type", "text/html")
-            .body(Cow::Owned(html_mutex_init.lock().as_bytes().to_vec()))
-            .unwrap()
-    })
-    .with_transparent(webview_options.transparent)
-    .with_autoplay(webview_options.autoplay)
-    .with_incognito(webview_options.incognito)
-    .with_clipboard(webview_options.clipboard)
-    .with_focused(webview_options.focused)
-    .with_devtools(webview_options.devtools)
-    .with_accept_first_mouse(webview_options.accept_first_mouse);
-    let ipc_tx = tx.clone();
-    if webview_options.ipc {
-        webview_builder = webview_builder.with_ipc_handler(move |message| {
-            ipc_tx
-                .send(Message::Notification(Notification::Ipc {
-                    message: message.body().to_string(),
-                }))
-                .unwrap()
-        })
     }
-    if let Some(initialization_script) = webview_options.initialization_script {
-        webview_builder =
-            webview_builder.with_initialization_script(initialization_script.as_str());
+
+    #[test]
+    fn test_throttle_coalesces_values() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(Duration::from_millis(100));
+
+        // The first value goes out immediately.
+        assert_eq!(throttle.push(1, start), Some(1));
+        assert_eq!(throttle.deadline(), None);
+
+        // Values within the interval are latched, keeping only the latest.
+        assert_eq!(throttle.push(2, start + Duration::from_millis(10)), None);
+        assert_eq!(throttle.push(3, start + Duration::from_millis(20)), None);
+        assert_eq!(
+            throttle.deadline(),
+            Some(start + Duration::from_millis(100))
+        );
+
+        // Nothing is flushed before the deadline.
+        assert_eq!(throttle.flush(start + Duration::from_millis(50)), None);
+
+        // The latest value is flushed once the interval has elapsed.
+        assert_eq!(throttle.flush(start + Duration::from_millis(100)), Some(3));
+        assert_eq!(throttle.deadline(), None);
+        assert_eq!(throttle.flush(start + Duration::from_millis(300)), None);
+
+        // After a quiet period values are emitted immediately again.
+        assert_eq!(
+            throttle.push(4, start + Duration::from_millis(300)),
+            Some(4)
+        );
     }
-    if let Some(user_agent) = webview_options.user_agent {
-        webview_builder = webview_builder.with_user_agent(user_agent.as_str());
+
+    #[test]
+    fn test_throttle_zero_interval() {
+        let start = Instant::now();
+        let mut throttle = Throttle::new(Duration::ZERO);
+
+        assert_eq!(throttle.push(1, start), Some(1));
+        assert_eq!(throttle.push(2, start), Some(2));
+        assert_eq!(throttle.deadline(), None);
     }
-    #[cfg(not(target_os = "linux"))]
-    let webview = webview_builder.build(&window)?;
 
-    #[cfg(target_os = "linux")]
-    let webview = {
-        use tao::platform::unix::WindowExtUnix;
-        use wry::WebViewBuilderExtUnix;
-        let vbox = window.default_vbox().unwrap();
-        webview_builder.build_gtk(vbox)?
-    };
+    #[test]
+    fn test_resolve_directory_path() {
+        let root = std::env::temp_dir().join(format!("webview-test-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("assets")).unwrap();
+        std::fs::write(root.join("index.html"), "<h1>Hello</h1>").unwrap();
+        std::fs::write(root.join("assets/app file.js"), "console.log(1)").unwrap();
+        let canonical_root = root.canonicalize().unwrap();
 
-    let notify_tx = tx.clone();
-    let notify = move |notification: Notification| {
-        debug!(notification = ?notification, "Sending notification to client");
-        notify_tx.send(Message::Notification(notification)).unwrap();
-    };
+        assert_eq!(
+            resolve_directory_path(&root, "/"),
+            Some(canonical_root.join("index.html"))
+        );
+        assert_eq!(
+            resolve_directory_path(&root, "/assets/app%20file.js"),
+            Some(canonical_root.join("assets/app file.js"))
+        );
+        assert_eq!(resolve_directory_path(&root, "/missing.html"), None);
+        assert_eq!(resolve_directory_path(&root, "/../../etc/passwd"), None);
+        assert_eq!(
+            resolve_directory_path(&root, "/assets/%2e%2e/%2e%2e/"),
+            None
+        );
 
-    let res_tx = tx.clone();
-    let res = move |response: Response| {
-        debug!(response = ?response, "Sending response to client");
-        res_tx.send(Message::Response(response)).unwrap();
-    };
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 
-    // Handle messages from the webview to the client.
-    process_output(std::io::stdout(), from_webview);
+    #[test]
+    fn test_process_input_echoes_requests() {
+        let (queue, receiver, responses) = test_queue(16, OverflowPolicy::Block);
+        let queue = RequestQueue {
+            echo_requests: true,
+            ..queue
+        };
+        let json = r#"{"$type":"getVersion","id":1} {"$type":"nope","id":2}"#;
+        process_input(BufReader::new(Cursor::new(json)), queue);
+        std::thread::sleep(std::time::Duration::from_millis(100));
 
-    // Handle messages from the client to the webview.
-    process_input(BufReader::new(std::io::stdin()), to_eventloop);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Request::GetVersion { id: 1 })
+        ));
+        let echoed: Vec<String> = responses
+            .try_iter()
+            .map(|message| match message {
+                Message::Notification(Notification::RequestReceived { raw }) => raw,
+                other => panic!("Expected a requestReceived notification, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            echoed,
+            [
+                r#"{"$type":"getVersion","id":1}"#,
+                r#"{"$type":"nope","id":2}"#
+            ]
+        );
+    }
 
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+    #[test]
+    fn test_request_queue_rejects_when_full() {
+        let (queue, receiver, responses) = test_queue(1, OverflowPolicy::Reject);
 
-        match event {
-            Event::NewEvents(StartCause::Init) => {
-                info!("Webview initialized");
-                notify(Notification::Started {
-                    version: VERSION.into(),
-                });
-            }
-            Event::UserEvent(event) => {
-                eprintln!("User event: {:?}", event);
-            }
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => {
-                info!("Webview close requested");
-                notify(Notification::Closed);
-                *control_flow = ControlFlow::Exit
-            }
-            Event::MainEventsCleared => {
-                if let Ok(req) = rx.try_recv() {
-                    debug!(request = ?req, "Processing request");
-                    match req {
-                        Request::Eval { id, js } => {
-                            let result = webview.evaluate_script(&js);
-                            res(match result {
-                                Ok(_) => Response::Ack { id },
-                                Err(err) => {
-                                    error!("Eval error: {:?}", err);
-                                    Response::Err {
-                                        id,
-                                        message: err.to_string(),
-                                    }
-                                }
-                            });
-                        }
-                        Request::SetTitle { id, title } => {
-                            window.set_title(title.as_str());
-                            res(Response::Ack { id });
-                        }
-                        Request::GetTitle { id } => res(Response::Result {
-                            id,
-                            result: window.title().into(),
-                        }),
-                        Request::OpenDevTools { id } => {
-                            #[cfg(feature = "devtools")]
-                            {
-                                webview.open_devtools();
-                                res(Response::Ack { id });
-                            }
-                            #[cfg(not(feature = "devtools"))]
-                            {
-                                res(Response::Err {
-                                    id,
-                                    message: "DevTools not enabled".to_string(),
-                                });
-                            }
-                        }
-                        Request::SetVisibility { id, visible } => {
-                            window.set_visible(visible);
-                            res(Response::Ack { id });
-                        }
-                        Request::IsVisible { id } => res(Response::Result {
-                            id,
-                            result: window.is_visible().into(),
-                        }),
-                        Request::GetVersion { id } => {
-                            res(Response::Result {
-                                id,
-                                result: VERSION.to_string().into(),
-                            });
-                        }
-                        Request::GetSize {
-                            id,
-                            include_decorations,
-                        } => {
-                            let size = if include_decorations.unwrap_or(false) {
-                                window.outer_size().to_logical(window.scale_factor())
-                            } else {
-                                window.inner_size().to_logical(window.scale_factor())
-                            };
-                            res(Response::Result {
-                                id,
-                                result: ResultType::Size(SizeWithScale {
-                                    width: size.width,
-                                    height: size.height,
-                                    scale_factor: window.scale_factor(),
-                                }),
-                            });
-                        }
-                        Request::SetSize { id, size } => {
-                            window.set_inner_size(dpi::Size::Logical(dpi::LogicalSize::new(
-                                size.width,
-                                size.height,
-                            )));
-                            res(Response::Ack { id });
-                        }
-                        Request::Fullscreen { id, fullscreen } => {
-                            let fullscreen = fullscreen.unwrap_or(window.fullscreen().is_none());
-                            eprintln!("Fullscreen: {:?}", fullscreen);
-                            if fullscreen {
-                                window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-                            } else {
-                                window.set_fullscreen(None);
-                            }
-                            res(Response::Ack { id });
-                        }
-                        Request::Maximize { id, maximized } => {
-                            let maximized = maximized.unwrap_or(!window.is_maximized());
-                            eprintln!("Maximize: {:?}", maximized);
-                            window.set_maximized(maximized);
-                            res(Response::Ack { id });
-                        }
-                        Request::Minimize { id, minimized } => {
-                            let minimized = minimized.unwrap_or(!window.is_minimized());
-                            eprintln!("Minimize: {:?}", minimized);
-                            window.set_minimized(minimized);
-                            res(Response::Ack { id });
-                        }
-                        Request::LoadHtml { id, html, origin } => {
-                            *html_mutex.lock() = html;
-                            let origin = match origin {
-                                Some(origin) => {
-                                    origin_mutex.lock().clone_from(&origin);
-                                    origin
-                                }
-                                None => origin_mutex.lock().clone(),
-                            };
-
-                            webview
-                                .load_url(&format!("load-html://{}?{}", origin, id))
-                                .unwrap();
-                            res(Response::Ack { id });
-                        }
-                        Request::LoadUrl { id, url, headers } => {
-                            let resp = match headers {
-                                Some(headers) => {
-                                    let headers = headers
-                                        .into_iter()
-                                        .map(|(k, v)| {
-                                            (
-                                                HeaderName::from_str(&k).unwrap(),
-                                                HeaderValue::from_str(&v).unwrap(),
-                                            )
-                                        })
-                                        .collect();
-                                    webview.load_url_with_headers(&url, headers)
-                                }
-                                None => webview.load_url(&url),
-                            };
-                            match resp {
-                                Ok(_) => res(Response::Ack { id }),
-                                Err(err) => res(Response::Err {
-                                    id,
-                                    message: err.to_string(),
-                                }),
-                            }
-                        }
-                    }
-                }
+        queue.push(Request::GetVersion { id: 1 });
+        queue.push(Request::GetVersion { id: 2 });
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Request::GetVersion { id: 1 })
+        ));
+        assert!(receiver.try_recv().is_err());
+        match responses.try_recv() {
+            Ok(Message::Response(Response::Err { id, message })) => {
+                assert_eq!(id, 2);
+                assert_eq!(message, "Request queue is full");
             }
-            _ => (),
+            other => panic!("Expected an error response, got {:?}", other),
         }
-    });
-}
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
+    #[test]
+    fn test_spawn_timeouts() {
+        let (expired, receiver) = mpsc::channel();
+        let timeouts = spawn_timeouts(move |value| expired.send((value, Instant::now())).unwrap());
+        let start = Instant::now();
+        timeouts
+            .send((start + Duration::from_millis(50), 1))
+            .unwrap();
+        timeouts
+            .send((start + Duration::from_millis(100), 2))
+            .unwrap();
+
+        let (value, at) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(value, 1);
+        assert!(at >= start + Duration::from_millis(50));
+        let (value, at) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(value, 2);
+        assert!(at >= start + Duration::from_millis(100));
+    }
 
     #[test]
-    fn test_process_input_simple() {
-        // Create a GetVersion request
-        let request = Request::GetVersion { id: 0 };
+    fn test_parse_headers_skips_invalid() {
+        let headers = parse_headers(HashMap::from([
+            (
+                "Content-Security-Policy".to_string(),
+                "default-src 'self'".to_string(),
+            ),
+            ("Invalid Name".to_string(), "value".to_string()),
+            ("X-Invalid-Value".to_string(), "line\nbreak".to_string()),
+        ]));
 
-        // Serialize to JSON
-        let json = serde_json::to_vec(&request).unwrap();
-        let cursor = Cursor::new(json);
-        let reader = BufReader::new(cursor);
-        let (sender, receiver) = mpsc::channel();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(
+            headers.get("content-security-policy").unwrap(),
+            "default-src 'self'"
+        );
+    }
 
-        // Capture stderr output
-        let stderr = std::io::stderr();
-        let _handle = stderr.lock();
+    #[test]
+    fn test_page_message_parse() {
+        let envelope =
+            |message: &str| format!(r#"{{"token":"{}","__webview":{}}}"#, page_token(), message);
+        let message =
+            PageMessage::parse(&envelope(r#"{"$type":"evalResult","id":3,"result":[1,2]}"#));
+        assert!(matches!(
+            message,
+            Some(PageMessage::EvalResult { id: 3, result }) if result == serde_json::json!([1, 2])
+        ));
 
-        process_input(reader, sender);
+        let message = PageMessage::parse(&envelope(
+            r#"{"$type":"evalError","id":4,"message":"nope"}"#,
+        ));
+        assert!(matches!(
+            message,
+            Some(PageMessage::EvalError { id: 4, message }) if message == "nope"
+        ));
+        assert!(matches!(
+            PageMessage::parse(&envelope(
+                r#"{"$type":"fullscreenChanged","fullscreen":true}"#
+            )),
+            Some(PageMessage::FullscreenChanged { fullscreen: true })
+        ));
 
-        // Give the thread a moment to process
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        // Messages without the token are the page's own, even when they look like ours.
+        assert!(PageMessage::parse(r#"{"__webview":{"$type":"startDrag"}}"#).is_none());
+        assert!(
+            PageMessage::parse(r#"{"token":"guess","__webview":{"$type":"startDrag"}}"#).is_none()
+        );
+        assert!(PageMessage::parse(r#"{"$type":"evalResult","id":3}"#).is_none());
+        assert!(PageMessage::parse("hello").is_none());
 
-        // Try to receive the message
-        match receiver.try_recv() {
-            Ok(received) => {
-                assert!(matches!(
-                    received,
-                    Request::GetVersion { id } if id == 0
-                ));
+        // The injected scripts post with the token.
+        let post = post_page_message(r#"{ $type: "startDrag" }"#);
+        assert!(post.contains(&format!(r#"token: "{}""#, page_token())));
+    }
+
+    #[test]
+    fn test_check_options() {
+        assert_eq!(check_options(r#"{"title":"Test","devtools":true}"#), vec![]);
+
+        let errors = check_options(r#"{"title":"Test","devTools":true}"#);
+        assert_eq!(
+            errors,
+            vec![OptionsError {
+                field: Some("devTools".into()),
+                message: "unknown field `devTools`".into(),
+            }]
+        );
+
+        let errors = check_options(r#"{"devtools":true}"#);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("title"));
+
+        assert_eq!(check_options("{").len(), 1);
+
+        let errors = check_options(r#"{"title":"Test","load":{"html":"","charset":"klingon"}}"#);
+        assert_eq!(
+            errors,
+            vec![OptionsError {
+                field: Some("load".into()),
+                message: "Unknown charset `klingon`".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_options_json() {
+        env::set_var(OPTIONS_ENV_VAR, r#"{"title":"From env"}"#);
+        assert_eq!(
+            options_json(None).as_deref(),
+            Some(r#"{"title":"From env"}"#)
+        );
+        assert_eq!(
+            options_json(Some(r#"{"title":"From argv"}"#.into())).as_deref(),
+            Some(r#"{"title":"From argv"}"#)
+        );
+        env::remove_var(OPTIONS_ENV_VAR);
+        assert_eq!(options_json(None), None);
+    }
+
+    #[test]
+    fn test_process_input_large_numbers() {
+        // Larger than `i64::MAX`, used to panic when round tripped through an integer.
+        let json =
+            r#"{"$type":"setSize","id":1,"size":{"width":9223372036854775808,"height":1.5e2}}"#;
+        let reader = BufReader::new(Cursor::new(json.as_bytes().to_vec()));
+        let (queue, receiver, _) = test_queue(16, OverflowPolicy::Block);
+
+        process_input(reader, queue);
+
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(Request::SetSize { id: 1, size }) => {
+                assert_eq!(size.width, 9223372036854775808.0);
+                assert_eq!(size.height, 150.0);
             }
-            Err(e) => panic!("Failed to receive message: {:?}", e),
+            other => panic!("Unexpected request: {:?}", other),
         }
     }
 
     #[test]
-    fn test_process_input_complex() {
-        // Create a SetSize request with nested SimpleSize
-        let request = Request::SetSize {
-            id: 0,
-            size: Size {
-                width: 800.0,
-                height: 600.0,
-            },
-        };
+    fn test_when_ready_result() {
+        let value = serde_json::json!({ "title": "Home", "links": 3 });
+        match json_result(1, Ok(value.clone())) {
+            Response::Result {
+                id: 1,
+                result: ResultType::Json(result),
+            } => assert_eq!(result, value),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+        match json_result(2, Err("Page not ready".into())) {
+            Response::Err { id: 2, message } => assert_eq!(message, "Page not ready"),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
 
-        // Serialize to JSON
-        let json = serde_json::to_vec(&request).unwrap();
-        let cursor = Cursor::new(json);
-        let reader = BufReader::new(cursor);
-        let (sender, receiver) = mpsc::channel();
+    #[test]
+    fn test_json_result_type() {
+        let response = Response::Result {
+            id: 1,
+            result: serde_json::json!([{ "name": "a", "size": 1.5 }, null]).into(),
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "$type": "result",
+                "id": 1,
+                "result": {
+                    "$type": "json",
+                    "value": [{ "name": "a", "size": 1.5 }, null],
+                },
+            })
+        );
+        match serde_json::from_value::<Response>(json).unwrap() {
+            Response::Result {
+                result: ResultType::Json(value),
+                ..
+            } => assert_eq!(
+                value,
+                serde_json::json!([{ "name": "a", "size": 1.5 }, null])
+            ),
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
 
-        process_input(reader, sender);
+    #[test]
+    fn test_protocol_response() {
+        let headers = HashMap::from([("Content-Type".to_string(), "text/plain".to_string())]);
+        let response = protocol_response(201, headers, "aGVsbG8=").unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/plain"
+        );
+        assert_eq!(response.body().as_ref(), b"hello");
 
-        // Give the thread a moment to process
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(protocol_response(200, HashMap::new(), "not base64!").is_err());
+        assert!(protocol_response(1000, HashMap::new(), "").is_err());
+    }
 
-        // Try to receive the message
-        match receiver.try_recv() {
-            Ok(received) => match received {
-                Request::SetSize { id, size } => {
-                    assert_eq!(id, 0);
-                    assert_eq!(size.width, 800.0);
-                    assert_eq!(size.height, 600.0);
-                }
-                other => panic!("Unexpected request type: {:?}", other),
+    /// One of every request, with strings that need escaping. Add new requests here so they're
+    /// covered by `test_process_input_every_request`.
+    fn every_request() -> Vec<Request> {
+        let tricky = r#"{"quoted": "va\"lue"}\n\t\\ {[}] ünïcødé"#.to_string();
+        vec![
+            Request::GetVersion { id: 0 },
+            Request::Eval {
+                id: 1,
+                js: format!("console.log({:?})", tricky),
+                frame: Some("iframe[name=\"inner\"]".into()),
+                binary: true,
             },
-            Err(e) => panic!("Failed to receive message: {:?}", e),
+            Request::SetTitle {
+                id: 2,
+                title: tricky.clone(),
+            },
+            Request::GetTitle {
+                id: 3,
+                source: TitleSource::Document,
+            },
+            Request::SetVisibility {
+                id: 4,
+                visible: false,
+            },
+            Request::IsVisible { id: 5 },
+            Request::SetWebviewVisible {
+                id: 48,
+                visible: false,
+            },
+            Request::IsWebviewVisible { id: 49 },
+            Request::Raise { id: 63 },
+            Request::DumpWindowState { id: 65 },
+            Request::SetCursorPosition {
+                id: 64,
+                x: 10.0,
+                y: 20.5,
+            },
+            Request::FocusWebview { id: 50 },
+            Request::IsWebviewFocused { id: 51 },
+            Request::OpenDevTools { id: 6 },
+            Request::CloseDevTools { id: 24 },
+            Request::IsDevToolsOpen { id: 25 },
+            Request::GetSize {
+                id: 7,
+                include_decorations: Some(true),
+            },
+            Request::SetSize {
+                id: 8,
+                size: Size {
+                    width: 1024.5,
+                    height: 768.0,
+                },
+            },
+            Request::SetResizeConstraints {
+                id: 32,
+                aspect_ratio: Some(16.0 / 9.0),
+                resize_increments: None,
+            },
+            Request::Fullscreen {
+                id: 9,
+                fullscreen: None,
+            },
+            Request::Maximize {
+                id: 10,
+                maximized: Some(true),
+            },
+            Request::Minimize {
+                id: 11,
+                minimized: Some(false),
+            },
+            Request::IsFullscreen { id: 26 },
+            Request::IsMaximized { id: 27 },
+            Request::IsMinimized { id: 28 },
+            Request::LoadHtml {
+                id: 12,
+                html: format!("<script>const x = {{ a: \"{}\" }};</script>", tricky),
+                origin: Some("example".into()),
+                charset: "iso-8859-1".into(),
+                replace: true,
+            },
+            Request::LoadUrl {
+                id: 13,
+                url: "https://example.com/?q=\"a b\"".into(),
+                headers: Some(HashMap::from([("X-Tricky".into(), tricky.clone())])),
+                referer: Some("https://example.com/".into()),
+                replace: false,
+            },
+            Request::LoadDirectory {
+                id: 14,
+                path: "/tmp/some dir".into(),
+                entry: "app.html".into(),
+            },
+            Request::PermissionDecision {
+                id: 15,
+                request_id: 3,
+                allow: true,
+            },
+            Request::ProtocolResponse {
+                id: 16,
+                request_id: 4,
+                status: 404,
+                headers: HashMap::from([("Content-Type".into(), "text/plain".into())]),
+                body: "aGVsbG8=".into(),
+            },
+            Request::Close { id: 17 },
+            Request::RequestRedraw { id: 34 },
+            Request::SetScrollBehavior {
+                id: 53,
+                smooth_scrolling: None,
+                overscroll: Some(false),
+            },
+            Request::SetScrollbarsVisible {
+                id: 58,
+                visible: false,
+            },
+            Request::SetFollowSystemTheme {
+                id: 66,
+                follow: false,
+                theme: Some(Theme::Dark),
+            },
+            Request::SetTextZoom {
+                id: 52,
+                factor: 1.25,
+            },
+            Request::MoveToMonitor {
+                id: 37,
+                monitor: "DELL U2720Q".into(),
+                maximize_to_work_area: true,
+            },
+            Request::SetTrafficLights {
+                id: 36,
+                inset: Some(Position { x: 12.0, y: 16.5 }),
+                visible: Some(false),
+            },
+            Request::SetAlwaysOnBottom {
+                id: 18,
+                always_on_bottom: true,
+            },
+            Request::SetIgnoreCursorEventsRegion {
+                id: 33,
+                interactive: vec![Rect {
+                    x: 10.0,
+                    y: 10.0,
+                    width: 120.5,
+                    height: 40.0,
+                }],
+            },
+            Request::WhenReady {
+                id: 19,
+                js: tricky.clone(),
+                timeout_ms: 500,
+            },
+            Request::AddScript {
+                id: 20,
+                script: tricky.clone(),
+            },
+            Request::RemoveScript {
+                id: 21,
+                script_id: 20,
+            },
+            Request::CaptureElement {
+                id: 22,
+                selector: "#app > .card[data-x=\"1\"]".into(),
+            },
+            Request::FindInPage {
+                id: 45,
+                query: tricky.clone(),
+                forward: false,
+                match_case: true,
+            },
+            Request::StopFind { id: 46 },
+            Request::ClipboardAction {
+                id: 47,
+                action: ClipboardAction::SelectAll,
+            },
+            Request::ReadClipboard { id: 55 },
+            Request::WriteClipboard {
+                id: 56,
+                text: tricky.clone(),
+            },
+            Request::GetSelection { id: 43 },
+            Request::ClearSelection { id: 44 },
+            Request::Click {
+                id: 42,
+                x: 10.0,
+                y: 20.5,
+            },
+            Request::Hello {
+                id: 38,
+                client_protocol_version: 1,
+            },
+            Request::GetPerformanceTiming { id: 54 },
+            Request::GetFrames { id: 59 },
+            Request::SetDragRegionHeight {
+                id: 60,
+                height: 32.0,
+            },
+            Request::GetDragRegionHeight { id: 61 },
+            Request::SetTouchEmulation {
+                id: 67,
+                enabled: true,
+            },
+            Request::RespondToPage {
+                id: 62,
+                rpc_id: 3,
+                data: serde_json::json!({ "ok": true }),
+                error: None,
+            },
+            Request::GetLastNavigationChain { id: 41 },
+            Request::ExportStorage { id: 39 },
+            Request::ImportStorage {
+                id: 40,
+                data: StorageSnapshot {
+                    origin: "https://example.com".into(),
+                    local_storage: HashMap::from([("theme".into(), tricky.clone())]),
+                    session_storage: HashMap::new(),
+                },
+            },
+            Request::RecreateWebview { id: 57 },
+            Request::Ping { id: 23 },
+            Request::Echo {
+                id: 35,
+                payload: serde_json::json!({ "nested": [tricky.clone(), 1.5, null, true] }),
+            },
+            Request::Batch {
+                id: 29,
+                requests: vec![
+                    Request::SetTitle {
+                        id: 30,
+                        title: tricky.clone(),
+                    },
+                    Request::IsVisible { id: 31 },
+                ],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_process_input_every_request() {
+        let requests = every_request();
+
+        // Make sure no request type is missing from the list.
+        let schema = serde_json::to_value(schemars::schema_for!(Request)).unwrap();
+        let mut expected_types = schema["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| variant["properties"]["$type"]["enum"][0].clone())
+            .collect::<Vec<_>>();
+        let mut covered_types = requests
+            .iter()
+            .map(|request| serde_json::to_value(request).unwrap()["$type"].clone())
+            .collect::<Vec<_>>();
+        expected_types.sort_by_key(|value| value.to_string());
+        covered_types.sort_by_key(|value| value.to_string());
+        assert_eq!(covered_types, expected_types);
+
+        let mut json = Vec::new();
+        for request in &requests {
+            json.extend(serde_json::to_vec(request).unwrap());
+            json.push(b'\n');
+        }
+        let (queue, receiver, _) = test_queue(requests.len(), OverflowPolicy::Block);
+
+        process_input(BufReader::new(Cursor::new(json)), queue);
+
+        for expected in &requests {
+            let received = receiver
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap_or_else(|_| panic!("Didn't receive {:?}", expected));
+            assert_eq!(
+                serde_json::to_value(&received).unwrap(),
+                serde_json::to_value(expected).unwrap()
+            );
+        }
+    }
+
+    /// A reader that yields a single byte per read, splitting input at every possible boundary.
+    struct OneByteReader(Cursor<Vec<u8>>);
+
+    impl Read for OneByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_process_input_byte_by_byte() {
+        let requests = every_request();
+        let mut json = Vec::new();
+        for request in &requests {
+            json.extend(serde_json::to_vec(request).unwrap());
+        }
+        let (queue, receiver, _) = test_queue(requests.len(), OverflowPolicy::Block);
+
+        process_input(BufReader::new(OneByteReader(Cursor::new(json))), queue);
+
+        for expected in &requests {
+            let received = receiver
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap_or_else(|_| panic!("Didn't receive {:?}", expected));
+            assert_eq!(
+                serde_json::to_value(&received).unwrap(),
+                serde_json::to_value(expected).unwrap()
+            );
         }
     }
 
     #[test]
-    fn test_process_output() {
-        let output = Arc::new(Mutex::new(Vec::new()));
-        let output_clone = output.clone();
-        let (sender, receiver) = mpsc::channel();
+    fn test_rate_limit() {
+        let start = Instant::now();
+        let mut rate_limit = RateLimit::default();
 
-        // Start processing output
-        process_output(WriteGuard(output_clone), receiver);
+        assert_eq!(rate_limit.allow(start, 2), (true, None));
+        assert_eq!(rate_limit.allow(start, 2), (true, None));
+        assert_eq!(rate_limit.allow(start, 2), (false, None));
+        assert_eq!(rate_limit.allow(start, 2), (false, None));
 
-        // Create and send a test message
-        let message = Message::Response(Response::Ack { id: 0 });
-        sender.send(message).unwrap();
+        // A new window lets records through again and reports what was dropped.
+        let later = start + Duration::from_secs(1);
+        assert_eq!(rate_limit.allow(later, 2), (true, Some(2)));
+        assert_eq!(
+            rate_limit.allow(later + Duration::from_secs(1), 2),
+            (true, None)
+        );
+    }
 
-        // Give the thread a moment to process
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    #[test]
+    fn test_window_size_with_state() {
+        let size: WindowSize =
+            serde_json::from_str(r#"{"state":"maximized","width":800,"height":600}"#).unwrap();
+        assert!(matches!(
+            size,
+            WindowSize::StateWithSize(WindowSizeStateWithSize {
+                state: WindowSizeStates::Maximized,
+                width: 800.0,
+                height: 600.0,
+            })
+        ));
+        let size: WindowSize = serde_json::from_str(r#"{"width":800,"height":600}"#).unwrap();
+        assert!(matches!(size, WindowSize::Size(_)));
+    }
 
-        // Check the output
-        let output_str = String::from_utf8(output.lock().clone()).unwrap();
-        let expected = serde_json::json!({
-            "$type": "response",
-            "data": {
-                "$type": "ack",
-                "id": 0
-            }
-        });
-        let expected_str = expected.to_string() + "\n";
-        assert_eq!(output_str, expected_str);
+    #[test]
+    fn test_with_default_headers() {
+        let defaults = HashMap::from([
+            ("Authorization".to_string(), "Bearer a".to_string()),
+            ("X-App".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(with_default_headers(&HashMap::new(), None), None);
+        assert_eq!(
+            with_default_headers(&defaults, None),
+            Some(defaults.clone())
+        );
+        assert_eq!(
+            with_default_headers(
+                &defaults,
+                Some(HashMap::from([(
+                    "Authorization".to_string(),
+                    "Bearer b".to_string()
+                )]))
+            ),
+            Some(HashMap::from([
+                ("Authorization".to_string(), "Bearer b".to_string()),
+                ("X-App".to_string(), "1".to_string()),
+            ]))
+        );
     }
 
-    // Helper struct to implement Write for our Arc<Mutex<Vec<u8>>>
-    struct WriteGuard(Arc<Mutex<Vec<u8>>>);
+    #[test]
+    fn test_url_filter() {
+        let filter = UrlFilter {
+            allow: vec!["example.com".into(), "*.example.com".into()],
+            deny: vec!["ads.example.com".into()],
+        };
+        assert!(filter.allows("https://example.com/"));
+        assert!(filter.allows("https://WWW.Example.com/page"));
+        assert!(!filter.allows("https://ads.example.com/banner"));
+        assert!(!filter.allows("https://notexample.com/"));
+        assert!(!filter.allows("http://example.org:8080/"));
+        assert!(filter.allows("about:blank"));
+        assert!(filter.allows("load-html://localhost"));
+        assert!(UrlFilter::default().allows("https://example.org/"));
+    }
 
-    impl Write for WriteGuard {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            self.0.lock().write(buf)
-        }
+    #[test]
+    fn test_find_state() {
+        let mut state = FindState::default();
+        assert_eq!(
+            state.search("needle", true, false),
+            FindStep::Start { forward: true }
+        );
+        assert_eq!(state.found(3), 1);
+        assert_eq!(state.search("needle", true, false), FindStep::Next);
+        assert_eq!(state.found(3), 2);
+        state.search("needle", true, false);
+        assert_eq!(state.found(3), 3);
+        state.search("needle", true, false);
+        assert_eq!(state.found(3), 1);
+        assert_eq!(state.search("needle", false, false), FindStep::Previous);
+        assert_eq!(state.found(3), 3);
+        // Changing the case sensitivity starts a new search.
+        assert_eq!(
+            state.search("needle", false, true),
+            FindStep::Start { forward: false }
+        );
+        assert_eq!(state.found(2), 2);
+        state.search("missing", true, true);
+        assert_eq!(state.found(0), 0);
+    }
 
-        fn flush(&mut self) -> std::io::Result<()> {
-            self.0.lock().flush()
+    #[test]
+    fn test_scroll_behavior_css() {
+        assert_eq!(ScrollBehavior::default().css(), "");
+        let css = ScrollBehavior {
+            smooth_scrolling: Some(false),
+            overscroll: Some(false),
+            scrollbars_hidden: true,
         }
+        .css();
+        assert!(css.contains("scroll-behavior: auto !important"));
+        assert!(css.contains("overscroll-behavior: none !important"));
+        assert!(css.contains("::-webkit-scrollbar { display: none !important; }"));
     }
 
     #[test]
-    fn test_process_input_multiple() {
-        // Create multiple requests
-        let requests = vec![
-            Request::GetVersion { id: 0 },
-            Request::SetSize {
-                id: 0,
-                size: Size {
-                    width: 1024.0,
-                    height: 768.0,
-                },
-            },
-            Request::LoadUrl {
-                id: 0,
-                url: "https://example.com".to_string(),
-                headers: Some(HashMap::from([
-                    ("User-Agent".to_string(), "test-agent".to_string()),
-                    ("Accept".to_string(), "text/html".to_string()),
-                ])),
-            },
-        ];
+    fn test_navigation_chain() {
+        let mut chain = NavigationChain::default();
+        chain.visit("http://example.com/");
+        chain.visit("http://example.com/");
+        chain.visit("https://example.com/");
+        chain.finish("https://example.com/home");
+        assert_eq!(
+            chain.urls,
+            [
+                "http://example.com/",
+                "https://example.com/",
+                "https://example.com/home"
+            ]
+        );
+        // The next navigation starts a new chain.
+        chain.visit("https://example.com/about");
+        assert_eq!(chain.urls, ["https://example.com/about"]);
+    }
 
-        // Serialize each request and concatenate
-        let mut json = Vec::new();
-        for request in &requests {
-            json.extend(serde_json::to_vec(request).unwrap());
-        }
+    #[test]
+    fn test_with_language() {
+        assert_eq!(
+            with_language(HashMap::new(), Some("de-DE")),
+            HashMap::from([("Accept-Language".to_string(), "de-DE".to_string())])
+        );
+        let defaults = HashMap::from([("accept-language".to_string(), "fr".to_string())]);
+        assert_eq!(with_language(defaults.clone(), Some("de-DE")), defaults);
+        assert_eq!(with_language(HashMap::new(), None), HashMap::new());
+    }
 
-        let cursor = Cursor::new(json);
-        let reader = BufReader::new(cursor);
-        let (sender, receiver) = mpsc::channel();
+    #[test]
+    fn test_with_referer() {
+        let headers = HashMap::from([("referer".to_string(), "https://a.test/".to_string())]);
+        assert_eq!(
+            with_referer(headers.clone(), Some("https://b.test/")),
+            HashMap::from([("Referer".to_string(), "https://b.test/".to_string())])
+        );
+        assert_eq!(with_referer(headers.clone(), None), headers);
+    }
 
-        process_input(reader, sender);
+    #[test]
+    fn test_panic_message() {
+        let panic = std::panic::catch_unwind(|| panic!("failed {}", 1)).unwrap_err();
+        assert_eq!(panic_message(panic.as_ref()), "failed 1");
+        let panic = std::panic::catch_unwind(|| std::panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(panic.as_ref()), "unknown panic");
+    }
 
-        // Give the thread a moment to process
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    #[test]
+    fn test_page_rpc() {
+        assert_eq!(ipc_rpc_id(r#"{"method":"save","__rpc_id":7}"#), Some(7));
+        assert_eq!(ipc_rpc_id(r#"{"method":"save"}"#), None);
+        assert_eq!(ipc_rpc_id("hello"), None);
+        assert_eq!(
+            respond_to_page_script(7, &serde_json::json!([1]), None),
+            "window.__webviewRpc && window.__webviewRpc(7, true, [1]);"
+        );
+        assert_eq!(
+            respond_to_page_script(7, &serde_json::Value::Null, Some("nope")),
+            r#"window.__webviewRpc && window.__webviewRpc(7, false, "nope");"#
+        );
+    }
 
-        // Try to receive all messages in order
-        for expected in requests {
-            match receiver.try_recv() {
-                Ok(received) => match (received, expected) {
-                    (Request::GetVersion { id: rid }, Request::GetVersion { id: eid }) => {
-                        assert_eq!(rid, eid);
-                    }
-                    (
-                        Request::SetSize {
-                            id: rid,
-                            size: rsize,
-                        },
-                        Request::SetSize {
-                            id: eid,
-                            size: esize,
-                        },
-                    ) => {
-                        assert_eq!(rid, eid);
-                        assert_eq!(rsize.width, esize.width);
-                        assert_eq!(rsize.height, esize.height);
-                    }
-                    (
-                        Request::LoadUrl {
-                            id: rid,
-                            url: rurl,
-                            headers: rheaders,
-                        },
-                        Request::LoadUrl {
-                            id: eid,
-                            url: eurl,
-                            headers: eheaders,
-                        },
-                    ) => {
-                        assert_eq!(rid, eid);
-                        assert_eq!(rurl, eurl);
-                        assert_eq!(rheaders, eheaders);
-                    }
-                    _ => panic!("Unexpected request type mismatch"),
-                },
-                Err(e) => panic!("Failed to receive message: {:?}", e),
-            }
-        }
+    #[test]
+    fn test_serve_html() {
+        let response = serve_html("<p>café ✓</p>", html_encoding("latin1").unwrap());
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "text/html; charset=windows-1252"
+        );
+        assert_eq!(&response.body()[..], b"<p>caf\xe9 &#10003;</p>");
 
-        // Verify no more messages
-        assert!(
-            receiver.try_recv().is_err(),
-            "Should not have any more messages"
+        let response = serve_html("<p>café</p>", html_encoding("UTF-16LE").unwrap());
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "text/html; charset=UTF-8"
         );
+        assert_eq!(&response.body()[..], "<p>café</p>".as_bytes());
+
+        assert!(html_encoding("klingon").is_err());
     }
 
     #[test]
-    fn test_process_output_multiple() {
-        let output = Arc::new(Mutex::new(Vec::new()));
-        let output_clone = output.clone();
-        let (sender, receiver) = mpsc::channel();
+    fn test_serve_manifest() {
+        let manifest = serde_json::json!({ "name": "Notes", "display": "standalone" });
+        let response = serve_manifest(manifest.to_string().as_bytes());
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/manifest+json"
+        );
+        let served: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(served, manifest);
 
-        // Start processing output
-        process_output(WriteGuard(output_clone), receiver);
+        let script = manifest_script(Some("#1e1e1e"));
+        assert!(script.contains(r#"link.href = "/__webview/manifest.webmanifest";"#));
+        assert!(script.contains(r##"const themeColor = "#1e1e1e";"##));
+        assert!(manifest_script(None).contains("const themeColor = null;"));
+    }
 
-        // Create and send multiple test messages
-        let messages = vec![
-            Message::Response(Response::Ack { id: 0 }),
-            Message::Notification(Notification::Started {
-                version: "1.0.0".to_string(),
-            }),
-            Message::Response(Response::Result {
-                id: 0,
-                result: ResultType::Size(SizeWithScale {
-                    width: 800.0,
-                    height: 600.0,
-                    scale_factor: 1.0,
-                }),
-            }),
-        ];
+    #[test]
+    fn test_font_face_css() {
+        let font = Font {
+            family: "My \"Font\"".into(),
+            path: None,
+            data: None,
+            weight: Some("100 900".into()),
+            style: Some("italic; color: red".into()),
+        };
+        assert_eq!(
+            font_face_css(&font, "webview-font://localhost/0"),
+            r#"@font-face { font-family: "My \"Font\""; src: url("webview-font://localhost/0"); font-weight: 100 900; }"#
+        );
+        assert_eq!(font_mime_type(b"wOF2\0\0"), "font/woff2");
+        assert_eq!(font_mime_type(b"\0\x01\0\0\0"), "font/ttf");
+        assert_eq!(font_mime_type(b"<svg"), "application/octet-stream");
+    }
 
-        // Send all messages
-        for message in messages.clone() {
-            sender.send(message).unwrap();
-        }
+    #[test]
+    fn test_add_nonce() {
+        assert_eq!(
+            add_nonce(
+                r#"<SCRIPT src="a.js"></SCRIPT><style>p {}</style><scripts>"#,
+                "abc"
+            ),
+            r#"<SCRIPT nonce="abc" src="a.js"></SCRIPT><style nonce="abc">p {}</style><scripts>"#
+        );
+        // Tags inside a script are part of its text.
+        assert_eq!(
+            add_nonce(r#"<script>"<script>"</script>"#, "abc"),
+            r#"<script nonce="abc">"<script>"</script>"#
+        );
+        assert_ne!(new_nonce(), new_nonce());
+    }
 
-        // Give the thread a moment to process
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    #[test]
+    fn test_unmapped_size() {
+        // Queried right after `started`, before the window's been mapped.
+        assert_eq!(
+            unmapped_size((0.0, 0.0), Some((800.0, 600.0))),
+            (800.0, 600.0)
+        );
+        // Nothing to fall back to when the window was created maximized.
+        assert_eq!(unmapped_size((0.0, 0.0), None), (0.0, 0.0));
+        // Once mapped, the window's own size is used.
+        assert_eq!(
+            unmapped_size((1024.0, 768.0), Some((800.0, 600.0))),
+            (1024.0, 768.0)
+        );
+    }
 
-        // Get the output and split by newlines
-        let output_str = String::from_utf8(output.lock().clone()).unwrap();
-        let received_messages: Vec<Message> = output_str
-            .lines()
-            .map(|line| serde_json::from_str(line).unwrap())
-            .collect();
+    #[test]
+    fn test_constrain_size() {
+        assert_eq!(constrain_size((803.0, 611.0), None, None), (803.0, 611.0));
+        assert_eq!(
+            constrain_size((803.0, 611.0), Some((8.0, 16.0)), None),
+            (800.0, 608.0)
+        );
+        assert_eq!(
+            constrain_size((803.0, 611.0), None, Some(16.0 / 9.0)),
+            (803.0, 452.0)
+        );
+        assert_eq!(
+            constrain_size((803.0, 611.0), Some((10.0, 10.0)), Some(2.0)),
+            (800.0, 400.0)
+        );
+        // Never snaps down to nothing.
+        assert_eq!(
+            constrain_size((3.0, 3.0), Some((8.0, 16.0)), None),
+            (8.0, 16.0)
+        );
+    }
 
-        // Verify we got all messages in order
-        assert_eq!(received_messages.len(), messages.len());
-        for (received, expected) in received_messages.iter().zip(messages.iter()) {
-            match (received, expected) {
-                (
-                    Message::Response(Response::Ack { id: rid }),
-                    Message::Response(Response::Ack { id: eid }),
-                ) => {
-                    assert_eq!(rid, eid);
-                }
-                (
-                    Message::Notification(Notification::Started { version: rver }),
-                    Message::Notification(Notification::Started { version: ever }),
-                ) => {
-                    assert_eq!(rver, ever);
-                }
-                (
-                    Message::Response(Response::Result {
-                        id: rid,
-                        result: rres,
-                    }),
-                    Message::Response(Response::Result {
-                        id: eid,
-                        result: eres,
-                    }),
-                ) => {
-                    assert_eq!(rid, eid);
-                    match (rres, eres) {
-                        (
-                            ResultType::Size(SizeWithScale {
-                                width: rw,
-                                height: rh,
-                                scale_factor: rs,
-                            }),
-                            ResultType::Size(SizeWithScale {
-                                width: ew,
-                                height: eh,
-                                scale_factor: es,
-                            }),
-                        ) => {
-                            assert_eq!(rw, ew);
-                            assert_eq!(rh, eh);
-                            assert_eq!(rs, es);
-                        }
-                        _ => panic!("Unexpected result type"),
-                    }
-                }
-                _ => panic!("Message type mismatch"),
-            }
-        }
+    #[test]
+    fn test_anchored_position() {
+        let monitor = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 1000.0,
+            height: 800.0,
+        };
+        let anchor = |placement, x, y| Anchor {
+            rect: Rect {
+                x,
+                y,
+                width: 100.0,
+                height: 20.0,
+            },
+            placement,
+            offset: 4.0,
+        };
 
-        // Verify each line is valid JSON
-        for line in output_str.lines() {
-            assert!(serde_json::from_str::<Message>(line).is_ok());
-        }
+        assert_eq!(
+            anchored_position(
+                &anchor(Placement::Below, 100.0, 100.0),
+                (200.0, 50.0),
+                &monitor
+            ),
+            (50.0, 124.0)
+        );
+        assert_eq!(
+            anchored_position(
+                &anchor(Placement::Above, 100.0, 100.0),
+                (200.0, 50.0),
+                &monitor
+            ),
+            (50.0, 46.0)
+        );
+        assert_eq!(
+            anchored_position(
+                &anchor(Placement::Right, 100.0, 100.0),
+                (200.0, 50.0),
+                &monitor
+            ),
+            (204.0, 85.0)
+        );
+        // Clamped to the monitor when it would overflow.
+        assert_eq!(
+            anchored_position(
+                &anchor(Placement::Left, 0.0, 790.0),
+                (200.0, 50.0),
+                &monitor
+            ),
+            (0.0, 750.0)
+        );
+        assert_eq!(
+            anchored_position(
+                &anchor(Placement::Below, 950.0, 100.0),
+                (200.0, 50.0),
+                &monitor
+            ),
+            (800.0, 124.0)
+        );
     }
 }